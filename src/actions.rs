@@ -1,148 +1,778 @@
-use casbin::{
-    error::{AdapterError, Error as CasbinError, ModelError},
-    Filter, Result,
+use casbin::Result;
+use rbatis::{
+    executor::{Executor, RBatisTxExecutor},
+    RBatis,
+};
+use rbs::Value;
+use std::collections::HashSet;
+
+use crate::dialect::Dialect;
+use crate::error::{commit_error, connection_error, query_error};
+use crate::filter::PolicyFilter;
+use crate::models::{
+    AuditRecord, CasbinRule, ChangeRecord, ConflictStrategy, PolicyChanges, RuleTable, AUDIT_CLEAR, CHANGE_ADD,
+    CHANGE_REMOVE, CHANGE_RESET,
 };
-use rbatis::RBatis;
-
-use crate::models::CasbinRule;
-
-pub async fn db_sync(rb: &rbatis::RBatis) -> Result<()> {
-    let driver_type = rb.driver_type().unwrap();
-
-    let sql = match driver_type {
-        "mysql" => include_str!("../sql/mysql.sql"),
-        "postgres" => include_str!("../sql/postgres.sql"),
-        "sqlite" => include_str!("../sql/sqlite.sql"),
-        "mssql" => include_str!("../sql/mssql.sql"),
-        _ => {
-            let err_msg = format!("unsupported driver type: {}, please create table casbin_rule manually. ", driver_type);
-            let err = CasbinError::from(ModelError::Other(err_msg));
-            return Err(err);
-        },
-    };
 
-    rb.exec(sql, vec![])
+pub(crate) async fn clear_policy(rb: &RBatis, table: &RuleTable) -> Result<()> {
+    let tx = rb
+        .acquire_begin()
         .await
-        .map(|_| {})
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))
+        .map_err(connection_error)?;
+    let result = clear_policy_in(&tx, table).await;
+    finish(tx, result).await
 }
- 
 
-pub(crate) async fn clear_policy(rb: &RBatis) -> Result<()> {
-    CasbinRule::delete_all(rb)
+// the writes of the `*_in` functions run in the transaction `tx` of the caller, which commits or rolls back.
+pub(crate) async fn clear_policy_in(tx: &dyn Executor, table: &RuleTable) -> Result<()> {
+    let result = CasbinRule::delete_all(tx, &table.name, table.tenant.as_deref())
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    Result::Ok(())
+        .map_err(query_error);
+    track(tx, table, result, |x| Changes::reset(x.rows_affected > 0)).await.map(|_| ())
 }
 
-pub(crate) async fn save_policy(rb: &RBatis, rules: Vec<CasbinRule>) -> Result<()> {
-    let mut tx = rb
+// replaces the stored rules in one transaction, so readers see either the old or the new rules.
+pub(crate) async fn save_policy(rb: &RBatis, table: &RuleTable, rules: Vec<CasbinRule>) -> Result<()> {
+    let tx = rb
         .acquire_begin()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    // CasbinRule::delete_all(&tx).await.map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    for rule in rules {
-        CasbinRule::insert(&tx, &rule)
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    }
-    tx.commit()
+        .map_err(connection_error)?;
+    let result = save_policy_in(&tx, table, rules).await;
+    finish(tx, result).await
+}
+
+pub(crate) async fn save_policy_in(tx: &dyn Executor, table: &RuleTable, rules: Vec<CasbinRule>) -> Result<()> {
+    let result = replace_rules(tx, table, &rules).await;
+    track(tx, table, result, |_| Changes {
+        reset: true,
+        added: rules.clone(),
+        ..Default::default()
+    })
+    .await
+}
+
+async fn replace_rules(rb: &dyn Executor, table: &RuleTable, rules: &[CasbinRule]) -> Result<()> {
+    CasbinRule::delete_all(rb, &table.name, table.tenant.as_deref())
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
+        .map_err(query_error)?;
+    insert_rules(rb, table, rules).await?;
     Ok(())
 }
 
-pub async fn remove_policy(rb: &RBatis, pt: &str, rule: Vec<String>) -> Result<bool> {
-    remove_policies(rb, pt, vec![rule]).await
+// writes only the difference between the stored rules and `rules` in one transaction.
+pub(crate) async fn save_policy_diff(rb: &RBatis, table: &RuleTable, rules: Vec<CasbinRule>) -> Result<PolicyChanges> {
+    let tx = rb
+        .acquire_begin()
+        .await
+        .map_err(connection_error)?;
+    let result = save_policy_diff_in(&tx, table, rules).await;
+    finish(tx, result).await
 }
 
-pub async fn remove_policies(rb: &RBatis, pt: &str, rules: Vec<Vec<String>>) -> Result<bool> {
-    let mut tx = rb
-        .acquire_begin()
+pub(crate) async fn save_policy_diff_in(tx: &dyn Executor, table: &RuleTable, rules: Vec<CasbinRule>) -> Result<PolicyChanges> {
+    let result = apply_diff(tx, table, rules).await;
+    track(tx, table, result, |(_, changes)| changes.clone()).await.map(|(counts, _)| counts)
+}
+
+async fn apply_diff(rb: &dyn Executor, table: &RuleTable, rules: Vec<CasbinRule>) -> Result<(PolicyChanges, Changes)> {
+    let mut stored = CasbinRule::select_all(rb, &table.name, table.tenant.as_deref())
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    let mut sum = 0;
-    for rule in rules {
-        let rule = normalize_casbin_rule(rule);
-        let r = CasbinRule::delete_policy(&tx, pt, &rule)
+        .map_err(query_error)?;
+    stored.iter_mut().for_each(|x| x.values.resize(table.arity, String::new()));
+    let wanted = rules.iter().map(|x| (x.ptype.as_str(), x.values.as_slice())).collect::<HashSet<_>>();
+
+    // a row which is not wanted, or a duplicate of a kept row, is stale
+    let mut kept = HashSet::new();
+    let mut stale = vec![];
+    for rule in &stored {
+        let key = (rule.ptype.as_str(), rule.values.as_slice());
+        if !(wanted.contains(&key) && kept.insert(key)) {
+            stale.push(rule);
+        }
+    }
+    let missing = rules
+        .iter()
+        .filter(|x| kept.insert((x.ptype.as_str(), x.values.as_slice())))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let ids = stale.iter().filter_map(|x| x.id).collect::<Vec<_>>();
+    let mut removed = 0;
+    for chunk in ids.chunks(table.dialect.max_bind_params()) {
+        let r = CasbinRule::delete_by_ids(rb, &table.name, table.tenant.as_deref(), chunk)
             .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-        sum += r.rows_affected;
+            .map_err(query_error)?;
+        removed += r.rows_affected;
     }
-    tx.commit()
+    let added = insert_rules(rb, table, &missing).await?;
+
+    // a removed duplicate row is no change of the rules
+    let changes = Changes {
+        removed: stale
+            .into_iter()
+            .filter(|x| !kept.contains(&(x.ptype.as_str(), x.values.as_slice())))
+            .cloned()
+            .collect(),
+        added: missing,
+        ..Default::default()
+    };
+    Ok((PolicyChanges { added, removed }, changes))
+}
+
+pub async fn remove_policy(rb: &RBatis, table: &RuleTable, pt: &str, rule: Vec<String>) -> Result<bool> {
+    remove_policies(rb, table, pt, vec![rule]).await
+}
+
+pub async fn remove_policies(rb: &RBatis, table: &RuleTable, pt: &str, rules: Vec<Vec<String>>) -> Result<bool> {
+    let tx = rb
+        .acquire_begin()
+        .await
+        .map_err(connection_error)?;
+    let result = remove_policies_in(&tx, table, pt, rules).await;
+    finish(tx, result).await
+}
+
+pub(crate) async fn remove_policies_in(tx: &dyn Executor, table: &RuleTable, pt: &str, rules: Vec<Vec<String>>) -> Result<bool> {
+    let result = delete_rules(tx, table, pt, rules).await;
+    track(tx, table, result, |removed| Changes::removed(removed.clone()))
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    Ok(sum > 0)
+        .map(|removed| !removed.is_empty())
+}
+
+// returns the removed rules.
+async fn delete_rules(rb: &dyn Executor, table: &RuleTable, pt: &str, rules: Vec<Vec<String>>) -> Result<Vec<CasbinRule>> {
+    let mut removed = vec![];
+    // a rule wider than the table can not be stored, so there is nothing to remove
+    for rule in rules.into_iter().filter(|x| x.len() <= table.arity) {
+        let rule = normalize_casbin_rule(rule, table.arity);
+        let r = CasbinRule::delete_policy(rb, &table.name, table.tenant.as_deref(), pt, &rule)
+            .await
+            .map_err(query_error)?;
+        if r.rows_affected > 0 {
+            removed.push(CasbinRule {
+                id: None,
+                ptype: pt.to_owned(),
+                values: rule,
+            });
+        }
+    }
+    Ok(removed)
 }
 
 pub async fn remove_filtered_policy(
     rb: &RBatis,
+    table: &RuleTable,
     pt: &str,
     field_index: usize,
     field_values: Vec<String>,
 ) -> Result<bool> {
-    let field_values = normalize_casbin_rule_option(field_values);
-
-    let result = match field_index {
-        1 => CasbinRule::delete_filtered_policy_1(rb, pt, &field_values).await,
-        2 => CasbinRule::delete_filtered_policy_2(rb, pt, &field_values).await,
-        3 => CasbinRule::delete_filtered_policy_3(rb, pt, &field_values).await,
-        4 => CasbinRule::delete_filtered_policy_4(rb, pt, &field_values).await,
-        5 => CasbinRule::delete_filtered_policy_5(rb, pt, &field_values).await,
-        _ => CasbinRule::delete_filtered_policy_0(rb, pt, &field_values).await,
+    let tx = rb
+        .acquire_begin()
+        .await
+        .map_err(connection_error)?;
+    let result = remove_filtered_policy_in(&tx, table, pt, field_index, field_values).await;
+    finish(tx, result).await
+}
+
+pub(crate) async fn remove_filtered_policy_in(
+    tx: &dyn Executor,
+    table: &RuleTable,
+    pt: &str,
+    field_index: usize,
+    field_values: Vec<String>,
+) -> Result<bool> {
+    // the columns before field_index are not filtered
+    let mut filter = vec![String::new(); field_index];
+    filter.extend(field_values);
+    let field_values = normalize_casbin_rule_option(filter, table.arity);
+
+    let result = delete_filtered_rules(tx, table, pt, &field_values).await;
+    track(tx, table, result, |(_, removed)| Changes::removed(removed.clone()))
+        .await
+        .map(|(sum, _)| sum > 0)
+}
+
+// returns the number of removed rows, and the removed rules when the writes are tracked.
+async fn delete_filtered_rules(
+    rb: &dyn Executor,
+    table: &RuleTable,
+    pt: &str,
+    field_values: &[Option<String>],
+) -> Result<(u64, Vec<CasbinRule>)> {
+    // the removed rules are only needed by the change log and the audit log
+    let removed = match table.logs_changes() {
+        true => CasbinRule::select_by_filter(rb, &table.name, table.tenant.as_deref(), pt, field_values)
+            .await
+            .map_err(query_error)?,
+        false => vec![],
     };
-    let result = result.map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    Ok(result.rows_affected > 0)
+    let r = CasbinRule::delete_filtered_policy(rb, &table.name, table.tenant.as_deref(), pt, field_values)
+        .await
+        .map_err(query_error)?;
+    Ok((r.rows_affected, removed))
+}
+
+// updates the `(old, new)` rule pairs in one transaction, returns the number of updated rows.
+pub(crate) async fn update_policies(
+    rb: &RBatis,
+    table: &RuleTable,
+    pt: &str,
+    pairs: Vec<(Vec<String>, Vec<String>)>,
+) -> Result<u64> {
+    let tx = rb
+        .acquire_begin()
+        .await
+        .map_err(connection_error)?;
+    let result = update_policies_in(&tx, table, pt, pairs).await;
+    finish(tx, result).await
+}
+
+pub(crate) async fn update_policies_in(
+    tx: &dyn Executor,
+    table: &RuleTable,
+    pt: &str,
+    pairs: Vec<(Vec<String>, Vec<String>)>,
+) -> Result<u64> {
+    let result = update_rules(tx, table, pt, pairs).await;
+    track(tx, table, result, |(removed, added)| Changes {
+        removed: removed.clone(),
+        added: added.clone(),
+        ..Default::default()
+    })
+    .await
+    .map(|(removed, _)| removed.len() as u64)
+}
+
+// returns the old and the new rules of the updated pairs.
+async fn update_rules(
+    rb: &dyn Executor,
+    table: &RuleTable,
+    pt: &str,
+    pairs: Vec<(Vec<String>, Vec<String>)>,
+) -> Result<(Vec<CasbinRule>, Vec<CasbinRule>)> {
+    let (mut removed, mut added) = (vec![], vec![]);
+    // an old rule wider than the table can not be stored, so there is nothing to update
+    for (old_rule, new_rule) in pairs.into_iter().filter(|(x, _)| x.len() <= table.arity) {
+        let old_rule = normalize_casbin_rule(old_rule, table.arity);
+        let new_rule = normalize_casbin_rule(new_rule, table.arity);
+        let r = CasbinRule::update_values(rb, &table.name, table.tenant.as_deref(), pt, &old_rule, &new_rule)
+            .await
+            .map_err(query_error)?;
+        if r.rows_affected > 0 {
+            let rule = |values| CasbinRule {
+                id: None,
+                ptype: pt.to_owned(),
+                values,
+            };
+            removed.push(rule(old_rule));
+            added.push(rule(new_rule));
+        }
+    }
+    Ok((removed, added))
+}
+
+// replaces the rules matching the filter with `new_rules` in one transaction, returns the replaced rules.
+pub(crate) async fn update_filtered_policies(
+    rb: &RBatis,
+    table: &RuleTable,
+    pt: &str,
+    new_rules: Vec<CasbinRule>,
+    field_index: usize,
+    field_values: Vec<String>,
+) -> Result<Vec<CasbinRule>> {
+    let tx = rb
+        .acquire_begin()
+        .await
+        .map_err(connection_error)?;
+    let result = update_filtered_policies_in(&tx, table, pt, new_rules, field_index, field_values).await;
+    finish(tx, result).await
+}
+
+pub(crate) async fn update_filtered_policies_in(
+    tx: &dyn Executor,
+    table: &RuleTable,
+    pt: &str,
+    new_rules: Vec<CasbinRule>,
+    field_index: usize,
+    field_values: Vec<String>,
+) -> Result<Vec<CasbinRule>> {
+    // the columns before field_index are not filtered
+    let mut filter = vec![String::new(); field_index];
+    filter.extend(field_values);
+    let field_values = normalize_casbin_rule_option(filter, table.arity);
+
+    let result = replace_filtered_rules(tx, table, pt, &new_rules, &field_values).await;
+    track(tx, table, result, |old_rules| Changes {
+        removed: old_rules.clone(),
+        added: new_rules.clone(),
+        ..Default::default()
+    })
+    .await
+}
+
+async fn replace_filtered_rules(
+    rb: &dyn Executor,
+    table: &RuleTable,
+    pt: &str,
+    new_rules: &[CasbinRule],
+    field_values: &[Option<String>],
+) -> Result<Vec<CasbinRule>> {
+    let old_rules = CasbinRule::select_by_filter(rb, &table.name, table.tenant.as_deref(), pt, field_values)
+        .await
+        .map_err(query_error)?;
+    CasbinRule::delete_filtered_policy(rb, &table.name, table.tenant.as_deref(), pt, field_values)
+        .await
+        .map_err(query_error)?;
+    insert_rules(rb, table, new_rules).await?;
+    Ok(old_rules)
+}
+
+/// A page of at most `limit` rules ordered by id, the rules after id `after`, or the first page for `None`.
+pub(crate) async fn load_page(rb: &RBatis, table: &RuleTable, after: Option<i32>, limit: usize) -> Result<Vec<CasbinRule>> {
+    let mut conditions = vec![];
+    let mut args = vec![];
+    if let Some(after) = after {
+        conditions.push("id > ?");
+        args.push(after.into());
+    }
+    if let Some(tenant) = &table.tenant {
+        conditions.push("tenant = ?");
+        args.push(tenant.as_str().into());
+    }
+    let condition = match conditions.is_empty() {
+        true => String::new(),
+        false => format!(" WHERE {}", conditions.join(" AND ")),
+    };
+    let sql = match table.dialect {
+        Dialect::Mssql => format!("SELECT TOP ({limit}) * FROM {}{condition} ORDER BY id", table.name),
+        _ => format!("SELECT * FROM {}{condition} ORDER BY id LIMIT {limit}", table.name),
+    };
+    rb.query_decode(&sql, args)
+        .await
+        .map_err(query_error)
+}
+
+/// The rules which match `filter`, see `PolicyFilter`.
+pub(crate) async fn load_filtered_policy(rb: &RBatis, table: &RuleTable, filter: &PolicyFilter) -> Result<Vec<CasbinRule>> {
+    let (condition, args) = filter.where_sql(table)?;
+    let sql = format!("SELECT * FROM {} WHERE {condition}", table.name);
+    rb.query_decode(&sql, args)
+        .await
+        .map_err(query_error)
 }
 
-pub(crate) async fn load_policy(rb: &RBatis) -> Result<Vec<CasbinRule>> {
-    let vec_rules = CasbinRule::select_all(rb)
+/// Deletes the rules which match `filter` in one transaction, returns the number of removed rows.
+pub(crate) async fn remove_where(rb: &RBatis, table: &RuleTable, filter: &PolicyFilter) -> Result<u64> {
+    let tx = rb
+        .acquire_begin()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    Result::Ok(vec_rules)
+        .map_err(connection_error)?;
+    let result = remove_where_in(&tx, table, filter).await;
+    finish(tx, result).await
 }
 
-pub(crate) async fn load_filtered_policy<'a>(rb: &RBatis, f: Filter<'a>) -> Result<Vec<CasbinRule>> {
-    let vec_rules = CasbinRule::select_filtered_policy(rb, f.g, f.p)
+pub(crate) async fn remove_where_in(tx: &dyn Executor, table: &RuleTable, filter: &PolicyFilter) -> Result<u64> {
+    let (condition, args) = filter.where_sql(table)?;
+    let result = delete_where(tx, table, &condition, args).await;
+    track(tx, table, result, |(_, removed)| Changes::removed(removed.clone()))
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    // println!("vec_rules: {vec_rules:?}");
-    Result::Ok(vec_rules)
+        .map(|(sum, _)| sum)
 }
 
-pub(crate) async fn add_policy(rb: &RBatis, new_rule: CasbinRule) -> Result<bool> {
-    CasbinRule::insert(rb, &new_rule)
+// returns the number of removed rows, and the removed rules when the writes are tracked.
+async fn delete_where(rb: &dyn Executor, table: &RuleTable, condition: &str, args: Vec<Value>) -> Result<(u64, Vec<CasbinRule>)> {
+    // the removed rules are only needed by the change log and the audit log
+    let removed = match table.logs_changes() {
+        true => {
+            let sql = format!("SELECT * FROM {} WHERE {condition}", table.name);
+            rb.query(&sql, args.clone())
+                .await
+                .and_then(rbatis::decode)
+                .map_err(query_error)?
+        }
+        false => vec![],
+    };
+    let sql = format!("DELETE FROM {} WHERE {condition}", table.name);
+    let r = rb
+        .exec(&sql, args)
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
-    Result::Ok(true)
+        .map_err(query_error)?;
+    Ok((r.rows_affected, removed))
+}
+
+pub(crate) async fn add_policy(rb: &RBatis, table: &RuleTable, new_rule: CasbinRule, conflict: ConflictStrategy) -> Result<bool> {
+    add_policies(rb, table, vec![new_rule], conflict).await
 }
 
-pub(crate) async fn add_policies(rb: &RBatis, rules: Vec<CasbinRule>) -> Result<bool> {
-    let mut tx = rb
+pub(crate) async fn add_policies(
+    rb: &RBatis,
+    table: &RuleTable,
+    rules: Vec<CasbinRule>,
+    conflict: ConflictStrategy,
+) -> Result<bool> {
+    let tx = rb
         .acquire_begin()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
+        .map_err(connection_error)?;
+    let result = add_policies_in(&tx, table, rules, conflict).await;
+    finish(tx, result).await
+}
 
-    for rule in rules {
-        CasbinRule::insert(&tx, &rule)
+pub(crate) async fn add_policies_in(
+    tx: &dyn Executor,
+    table: &RuleTable,
+    rules: Vec<CasbinRule>,
+    conflict: ConflictStrategy,
+) -> Result<bool> {
+    let result = match conflict {
+        ConflictStrategy::Error => insert_rules(tx, table, &rules).await.map(|sum| (sum, rules)),
+        // the change log and the audit log record the inserted rules only
+        _ if table.logs_changes() => insert_returning_new_rules(tx, table, rules)
             .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))?;
+            .map(|inserted| (inserted.len() as u64, inserted)),
+        _ => insert_new_rules(tx, table, &rules).await.map(|sum| (sum, rules)),
+    };
+    let (sum, _) = track(tx, table, result, |(sum, rules)| match sum {
+        0 => Changes::default(),
+        _ => Changes::added(rules.clone()),
+    })
+    .await?;
+    Ok(conflict != ConflictStrategy::Report || sum > 0)
+}
+
+// adds the rules in one transaction and returns the inserted ones, the stored ones are skipped
+// unless `conflict` is `Error`, see `insert_returning_new_rules`.
+pub(crate) async fn add_new_policies(
+    rb: &RBatis,
+    table: &RuleTable,
+    rules: Vec<CasbinRule>,
+    conflict: ConflictStrategy,
+) -> Result<Vec<CasbinRule>> {
+    let tx = rb
+        .acquire_begin()
+        .await
+        .map_err(connection_error)?;
+    let result = add_new_policies_in(&tx, table, rules, conflict).await;
+    finish(tx, result).await
+}
+
+pub(crate) async fn add_new_policies_in(
+    tx: &dyn Executor,
+    table: &RuleTable,
+    rules: Vec<CasbinRule>,
+    conflict: ConflictStrategy,
+) -> Result<Vec<CasbinRule>> {
+    let result = match conflict {
+        ConflictStrategy::Error => insert_rules(tx, table, &rules).await.map(|_| rules),
+        ConflictStrategy::Skip | ConflictStrategy::Report => insert_returning_new_rules(tx, table, rules).await,
+    };
+    track(tx, table, result, |inserted| Changes::added(inserted.clone())).await
+}
+
+// the rules a write changed, they are logged in the order reset, removed, added.
+#[derive(Clone, Default)]
+struct Changes {
+    reset: bool,
+    removed: Vec<CasbinRule>,
+    added: Vec<CasbinRule>,
+}
+
+impl Changes {
+    fn reset(reset: bool) -> Self {
+        Self {
+            reset,
+            ..Default::default()
+        }
     }
-    tx.commit()
+
+    fn removed(removed: Vec<CasbinRule>) -> Self {
+        Self {
+            removed,
+            ..Default::default()
+        }
+    }
+
+    fn added(added: Vec<CasbinRule>) -> Self {
+        Self {
+            added,
+            ..Default::default()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.reset && self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+// logs the changes of `result` in the write transaction: bumps the revision and writes the change log when the writes
+// are tracked, and writes the audit log when they are audited. `changes` is only called then.
+async fn track<T>(rb: &dyn Executor, table: &RuleTable, result: Result<T>, changes: impl FnOnce(&T) -> Changes) -> Result<T> {
+    let Ok(v) = &result else {
+        return result;
+    };
+    if !table.logs_changes() {
+        return result;
+    }
+    let changes = changes(v);
+    if changes.is_empty() {
+        return result;
+    }
+    if table.tracking.is_some() {
+        log_changes(rb, table, &changes).await?;
+    }
+    if table.audit.is_some() {
+        log_audit(rb, table, &changes).await?;
+    }
+    result
+}
+
+// an empty rule, recorded for the operations on all rules.
+fn empty_rule(table: &RuleTable) -> CasbinRule {
+    CasbinRule {
+        id: None,
+        ptype: String::new(),
+        values: vec![String::new(); table.arity],
+    }
+}
+
+async fn log_changes(rb: &dyn Executor, table: &RuleTable, changes: &Changes) -> Result<()> {
+    let Some(tracking) = &table.tracking else {
+        return Ok(());
+    };
+    let sql = format!("UPDATE {} SET revision = revision + 1 WHERE id = 1", tracking.revision_table);
+    rb.exec(&sql, vec![])
+        .await
+        .map_err(query_error)?;
+    // the row is locked by the update until the transaction ends
+    let sql = format!("SELECT revision FROM {} WHERE id = 1", tracking.revision_table);
+    let revision: i64 = rb
+        .query(&sql, vec![])
         .await
-        .map(|_| true)
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(err))))
+        .and_then(rbatis::decode)
+        .map_err(query_error)?;
+
+    let head = format!(
+        "INSERT INTO {} (revision, operation, {}) VALUES ",
+        tracking.changes_table,
+        table.rule_columns()
+    );
+    // a reset reloads all rules, so the rules of the revision are not needed
+    let reset = [empty_rule(table)];
+    let records = match changes.reset {
+        true => vec![(CHANGE_RESET, &reset[..])],
+        false => vec![(CHANGE_REMOVE, &changes.removed[..]), (CHANGE_ADD, &changes.added[..])],
+    };
+    for (operation, rules) in records {
+        for chunk in rules.chunks(table.chunk_size(table.arity + 3)) {
+            CasbinRule::insert_changes(rb, &head, revision, operation, table.tenant.as_deref(), chunk)
+                .await
+                .map_err(query_error)?;
+        }
+    }
+    Ok(())
+}
+
+async fn log_audit(rb: &dyn Executor, table: &RuleTable, changes: &Changes) -> Result<()> {
+    let Some(audit) = &table.audit else {
+        return Ok(());
+    };
+    let head = format!(
+        "INSERT INTO {} (operation, actor, {}) VALUES ",
+        audit.table,
+        table.rule_columns()
+    );
+    let clear = match changes.reset {
+        true => vec![empty_rule(table)],
+        false => vec![],
+    };
+    let records = [
+        (AUDIT_CLEAR, &clear[..]),
+        (CHANGE_REMOVE, &changes.removed[..]),
+        (CHANGE_ADD, &changes.added[..]),
+    ];
+    for (operation, rules) in records {
+        for chunk in rules.chunks(table.chunk_size(table.arity + 3)) {
+            CasbinRule::insert_audit(rb, &head, operation, audit.actor.as_deref(), table.tenant.as_deref(), chunk)
+                .await
+                .map_err(query_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// The current revision of the rules, `revision_table` is the quoted revision table.
+pub(crate) async fn revision(rb: &RBatis, revision_table: &str) -> Result<i64> {
+    let sql = format!("SELECT revision FROM {} WHERE id = 1", revision_table);
+    rb.query_decode(&sql, vec![])
+        .await
+        .map_err(query_error)
+}
+
+/// The changes after revision `from` up to revision `to`, in the order they were made.
+pub(crate) async fn load_changes(rb: &RBatis, changes_table: &str, from: i64, to: i64) -> Result<Vec<ChangeRecord>> {
+    let sql = format!(
+        "SELECT * FROM {} WHERE revision > ? AND revision <= ? ORDER BY id",
+        changes_table
+    );
+    rb.query_decode(&sql, vec![from.into(), to.into()])
+        .await
+        .map_err(query_error)
+}
+
+/// A page of the audit log, the entries after id `after` in the order they were written.
+/// With a `tenant` only the entries of its writes.
+pub(crate) async fn load_audit(
+    rb: &RBatis,
+    dialect: Dialect,
+    audit_table: &str,
+    tenant: Option<&str>,
+    after: i64,
+    limit: u64,
+) -> Result<Vec<AuditRecord>> {
+    let mut args = vec![after.into()];
+    let scope = match tenant {
+        Some(tenant) => {
+            args.push(tenant.into());
+            " AND tenant = ?"
+        }
+        None => "",
+    };
+    let sql = match dialect {
+        Dialect::Mssql => format!(
+            "SELECT TOP ({limit}) * FROM {} WHERE id > ?{scope} ORDER BY id",
+            audit_table
+        ),
+        _ => format!("SELECT * FROM {} WHERE id > ?{scope} ORDER BY id LIMIT {limit}", audit_table),
+    };
+    rb.query_decode(&sql, args)
+        .await
+        .map_err(query_error)
+}
+
+/// Deletes the changes up to `revision`, returns the number of deleted rows.
+pub(crate) async fn prune_changes(rb: &RBatis, changes_table: &str, revision: i64) -> Result<u64> {
+    let sql = format!("DELETE FROM {} WHERE revision <= ?", changes_table);
+    let r = rb
+        .exec(&sql, vec![revision.into()])
+        .await
+        .map_err(query_error)?;
+    Ok(r.rows_affected)
+}
+
+// commits the transaction when `result` is ok, otherwise rolls it back.
+pub(crate) async fn finish<T>(tx: RBatisTxExecutor, result: Result<T>) -> Result<T> {
+    match result {
+        Ok(v) => {
+            tx.commit()
+                .await
+                .map_err(commit_error)?;
+            Ok(v)
+        }
+        Err(err) => {
+            let _ = tx.rollback().await;
+            Err(err)
+        }
+    }
+}
+
+// inserts the rules with multi-row statements of `table.insert_chunk_size()` rows.
+async fn insert_rules(rb: &dyn Executor, table: &RuleTable, rules: &[CasbinRule]) -> Result<u64> {
+    let (head, tail) = table.insert_sql(false)?;
+    insert_chunks(rb, table, &head, &tail, rules).await
+}
+
+// like `insert_rules`, but the stored rules are skipped. returns the number of inserted rows.
+async fn insert_new_rules(rb: &dyn Executor, table: &RuleTable, rules: &[CasbinRule]) -> Result<u64> {
+    let (head, tail) = table.insert_sql(true)?;
+    // a merge fails on duplicates inside its source rows
+    let rules = distinct_rules(rules.to_vec());
+    insert_chunks(rb, table, &head, &tail, &rules).await
+}
+
+// inserts the rules with multi-row statements, skipping the stored ones, and returns the inserted ones.
+// the stored rules of each chunk are selected before it is inserted, to tell the inserted ones apart.
+async fn insert_returning_new_rules(rb: &dyn Executor, table: &RuleTable, rules: Vec<CasbinRule>) -> Result<Vec<CasbinRule>> {
+    let (head, tail) = table.insert_sql(true)?;
+    let rules = distinct_rules(rules);
+    let mut inserted = vec![];
+    for chunk in rules.chunks(table.insert_chunk_size()) {
+        let stored = stored_rules(rb, table, chunk).await?;
+        let new_rules = chunk
+            .iter()
+            .filter(|x| !stored.contains(&(x.ptype.clone(), x.values.clone())))
+            .cloned()
+            .collect::<Vec<_>>();
+        if new_rules.is_empty() {
+            continue;
+        }
+        // the statement still skips a rule which a concurrent write stored after the select
+        CasbinRule::insert_batch(rb, &head, &tail, table.tenant.as_deref(), &new_rules)
+            .await
+            .map_err(query_error)?;
+        inserted.extend(new_rules);
+    }
+    Ok(inserted)
+}
+
+// the `(ptype, values)` of the stored rules among `rules`. they are selected in chunks of
+// `STORED_CHUNK_SIZE`, an `OR` of more conditions may exceed the expression depth limit of sqlite.
+async fn stored_rules(rb: &dyn Executor, table: &RuleTable, rules: &[CasbinRule]) -> Result<HashSet<(String, Vec<String>)>> {
+    const STORED_CHUNK_SIZE: usize = 100;
+    let rule = format!(
+        "(ptype = ?{})",
+        (0..table.arity).map(|i| format!(" AND v{i} = ?")).collect::<String>()
+    );
+    let mut stored = HashSet::new();
+    for chunk in rules.chunks(STORED_CHUNK_SIZE) {
+        let mut args = table.tenant.iter().map(|x| Value::from(x.as_str())).collect::<Vec<_>>();
+        for rule in chunk {
+            args.push(rule.ptype.as_str().into());
+            args.extend(rule.values.iter().map(|x| Value::from(x.as_str())));
+        }
+        let mut condition = vec![rule.as_str(); chunk.len()].join(" OR ");
+        if table.tenant.is_some() {
+            condition = format!("tenant = ? AND ({condition})");
+        }
+        let sql = format!("SELECT ptype, {} FROM {} WHERE {condition}", table.value_columns(), table.name);
+        let rows: Vec<CasbinRule> = rb
+            .query(&sql, args)
+            .await
+            .and_then(rbatis::decode)
+            .map_err(query_error)?;
+        stored.extend(rows.into_iter().map(|x| (x.ptype, x.values)));
+    }
+    Ok(stored)
+}
+
+async fn insert_chunks(rb: &dyn Executor, table: &RuleTable, head: &str, tail: &str, rules: &[CasbinRule]) -> Result<u64> {
+    let mut sum = 0;
+    for chunk in rules.chunks(table.insert_chunk_size()) {
+        let r = CasbinRule::insert_batch(rb, head, tail, table.tenant.as_deref(), chunk)
+            .await
+            .map_err(query_error)?;
+        sum += r.rows_affected;
+    }
+    Ok(sum)
+}
+
+// drops the repeated rules, keeping the order.
+pub(crate) fn distinct_rules(rules: Vec<CasbinRule>) -> Vec<CasbinRule> {
+    let mut seen = HashSet::new();
+    rules
+        .into_iter()
+        .filter(|x| seen.insert((x.ptype.clone(), x.values.clone())))
+        .collect()
 }
 
-// resize the vec to 6 fields. fill it with empty string.
-fn normalize_casbin_rule(mut rule: Vec<String>) -> Vec<String> {
-    rule.resize(6, String::new());
+// resize the vec to `arity` fields. fill it with empty string.
+fn normalize_casbin_rule(mut rule: Vec<String>, arity: usize) -> Vec<String> {
+    rule.resize(arity, String::new());
     rule
 }
 
 // if the field value is empty, set it to None, otherwise set it to Some(value),
-// and resize the vec to 6 fields.
-fn normalize_casbin_rule_option(rule: Vec<String>) -> Vec<Option<String>> {
+// and resize the vec to `arity` fields.
+fn normalize_casbin_rule_option(rule: Vec<String>, arity: usize) -> Vec<Option<String>> {
     let mut rule_with_option = rule
         .iter()
         .map(|x| match x.is_empty() {
@@ -150,7 +780,7 @@ fn normalize_casbin_rule_option(rule: Vec<String>) -> Vec<Option<String>> {
             false => Some(x.clone()),
         })
         .collect::<Vec<Option<String>>>();
-    rule_with_option.resize(6, None);
+    rule_with_option.resize(arity, None);
     rule_with_option
 }
 
@@ -162,7 +792,7 @@ mod test {
     #[test]
     fn test_normalize_casbin_rule() {
         let rule = to_vec!["alice", "data1", "write"];
-        let new_rule = normalize_casbin_rule(rule.clone());
+        let new_rule = normalize_casbin_rule(rule.clone(), 6);
         println!("{new_rule:?}");
         assert!(new_rule.len() == 6);
         assert_eq!(new_rule[0], "alice".to_string());
@@ -172,7 +802,7 @@ mod test {
         assert_eq!(new_rule[4], "".to_string());
         assert_eq!(new_rule[5], "".to_string());
 
-        let new_rule = normalize_casbin_rule_option(rule);
+        let new_rule = normalize_casbin_rule_option(rule, 6);
         println!("{new_rule:?}");
         assert!(new_rule.len() == 6);
         assert_eq!(new_rule[0], Some("alice".to_string()));
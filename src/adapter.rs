@@ -1,13 +1,26 @@
 use crate::actions as adapter;
+use crate::builder::RbatisAdapterBuilder;
+use crate::csv;
+use crate::dialect::Dialect;
+use crate::error::Error;
+use crate::filter::PolicyFilter;
+use crate::ddl;
+use crate::migrations;
+use crate::schema;
+use crate::transaction::TxAdapter;
 use crate::models::*;
 use crate::utils::*;
 use async_trait::async_trait;
+use futures::{stream, Stream, TryStreamExt};
 use casbin::{Adapter, Filter, Model, Result};
-use rbatis::RBatis;
+use rbatis::{executor::Executor, RBatis};
+use std::collections::{BTreeSet, HashSet};
+use std::io::{BufRead, Write};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex,
 };
+use std::time::{Duration, Instant};
 #[cfg(feature = "tracing")]
 #[allow(unused_imports)]
 use tracing::instrument;
@@ -22,81 +35,695 @@ macro_rules! debug {
 #[derive(Clone)]
 pub struct RbatisAdapter {
     pool: rbatis::RBatis,
+    replica: Option<rbatis::RBatis>,
+    primary_reads_after_write: Option<Duration>,
+    last_write: Arc<Mutex<Option<Instant>>>,
+    table_name: String,
+    arity: usize,
+    batch_size: usize,
+    page_size: usize,
+    pub(crate) save_mode: SaveMode,
+    pub(crate) conflict: ConflictStrategy,
+    strict: bool,
+    lengths: ColumnLengths,
+    field_indexes: BTreeSet<usize>,
+    track_revision: bool,
+    audit: bool,
+    actor: Option<String>,
+    tenant: Option<String>,
+    all_tenants: bool,
+    tenant_column: Arc<AtomicBool>,
     is_filtered: Arc<AtomicBool>,
 }
 
-impl<'a> RbatisAdapter {
-    /// Creates a new CasbinRbatisAdapter instance.
+impl RbatisAdapter {
+    /// Creates a new CasbinRbatisAdapter instance, `rb` is used for the reads and the writes, see [`RbatisAdapter::with_replica`].
     pub async fn new(rb: &RBatis) -> Result<Self> {
         let this = Self {
             pool: rb.clone(),
+            replica: None,
+            primary_reads_after_write: None,
+            last_write: Arc::new(Mutex::new(None)),
+            table_name: TABLE_NAME.to_owned(),
+            arity: ARITY,
+            batch_size: BATCH_SIZE,
+            page_size: PAGE_SIZE,
+            save_mode: SaveMode::default(),
+            conflict: ConflictStrategy::default(),
+            strict: false,
+            lengths: ColumnLengths::default(),
+            field_indexes: BTreeSet::new(),
+            track_revision: false,
+            audit: false,
+            actor: None,
+            tenant: None,
+            all_tenants: false,
+            tenant_column: Arc::new(AtomicBool::new(false)),
             is_filtered: Arc::new(AtomicBool::new(false)),
         };
         Ok(this)
     }
 
-    /// Synchronize the database schema. It will create the table if not exist.
-    pub async fn db_sync(&self) -> Result<()> {
-        adapter::db_sync(&self.pool).await
+    /// A builder of an adapter on `rb`, which validates the options, see [`RbatisAdapterBuilder`].
+    pub fn builder(rb: &RBatis) -> RbatisAdapterBuilder {
+        RbatisAdapterBuilder::new(rb)
     }
-}
 
-#[async_trait]
-impl Adapter for RbatisAdapter {
-    #[cfg_attr(feature = "tracing", instrument(skip(self, m), err))]
-    async fn load_policy(&mut self, m: &mut dyn Model) -> Result<()> {
-        let rules = adapter::load_policy(&self.pool).await?;
+    /// Use `table_name` to store the rules instead of the default `casbin_rule`.
+    /// It can be qualified with a schema, like `auth.casbin_rule`.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_table_name("svc_a_casbin_rule");
+    /// ```
+    pub fn with_table_name(mut self, table_name: &str) -> Self {
+        self.table_name = table_name.to_owned();
+        self.tenant_column = Arc::new(AtomicBool::new(false));
+        self
+    }
 
-        for casbin_rule in &rules {
-            if casbin_rule.ptype.is_none() {
-                continue;
+    /// The name of the table where the rules are stored.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Store the rules in `arity` value columns (`v0..v{arity-1}`) instead of the default 6.
+    /// Adding a rule with more fields than `arity` is an error. The unique key covers every value column,
+    /// so `db_sync` fails with more than 7 on mysql and more than 6 on mssql.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_arity(10);
+    /// ```
+    pub fn with_arity(mut self, arity: usize) -> Self {
+        self.arity = arity.max(1);
+        self
+    }
+
+    /// The number of value columns of the table.
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// The lengths of the `ptype` and `v*` columns, 12 and 128 characters by default like the tables of `db_sync`.
+    /// A longer ptype or value is rejected with [`Error::ValueTooLong`] before anything is written,
+    /// raise the lengths for a table whose columns are wider.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_max_lengths(32, 255);
+    /// ```
+    pub fn with_max_lengths(mut self, ptype_len: usize, value_len: usize) -> Self {
+        self.lengths = ColumnLengths {
+            ptype: ptype_len.max(1),
+            value: value_len.max(1),
+        };
+        self
+    }
+
+    /// Insert at most `batch_size` rules with one statement in `save_policy` and `add_policies`, the default is 1000.
+    /// The rows of a statement are also bounded by the bind parameter limit of the driver, e.g. 2100 for mssql.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Read at most `page_size` rules per query in `load_policy` and `stream_policy`, 10000 by default.
+    /// The pages are read by id, so the rules of a page are added to the model before the next page is read.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_page_size(1000);
+    /// ```
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// The stored rules as `(ptype, rule)` pairs in the order of their ids, read page by page,
+    /// for the callers which process the rules without building a model.
+    /// ```rust,ignore
+    /// let mut rules = adapter.stream_policy();
+    /// while let Some((ptype, rule)) = rules.try_next().await? {
+    ///     println!("{ptype}, {}", rule.join(", "));
+    /// }
+    /// ```
+    pub fn stream_policy(&self) -> impl Stream<Item = Result<(String, Vec<String>)>> + Send + 'static {
+        let this = self.clone();
+        // all pages are read from the same pool
+        let rb = self.reader().clone();
+        // the state is the id to read after, `None` when all pages were read
+        let pages = stream::try_unfold(Some(None), move |after: Option<Option<i32>>| {
+            let this = this.clone();
+            let rb = rb.clone();
+            async move {
+                let Some(after) = after else {
+                    return Result::Ok(None);
+                };
+                let page = adapter::load_page(&rb, &this.table()?, after, this.page_size).await?;
+                let next = next_page(&page, this.page_size)?.map(Some);
+                Ok(Some((page, next)))
             }
-            let ptype = casbin_rule.ptype.as_ref().unwrap();
-            if let Some(ref sec) = ptype.chars().next().map(|x| x.to_string()) {
-                if let Some(rule) = normalize_policy(casbin_rule) {
-                    if let Some(t1) = m.get_mut_model().get_mut(sec) {
-                        if let Some(t2) = t1.get_mut(ptype) {
-                            t2.get_mut_policy().insert(rule);
-                        }
-                    }
+        });
+        pages
+            .map_ok(|page| {
+                stream::iter(page.into_iter().filter_map(|rule| {
+                    normalize_policy(&rule).map(|values| Ok((rule.ptype, values)))
+                }))
+            })
+            .try_flatten()
+    }
+
+    /// Writes the stored rules to `writer` in casbin's csv policy format, like `examples/rbac_policy.csv`,
+    /// and returns the number of rules. The values with a comma, a quote or surrounding whitespace are quoted.
+    /// ```rust,ignore
+    /// let n = adapter.export_csv(BufWriter::new(File::create("policy.csv")?)).await?;
+    /// ```
+    pub async fn export_csv<W: Write>(&self, mut writer: W) -> Result<u64> {
+        let mut rules = Box::pin(self.stream_policy());
+        let mut count = 0;
+        while let Some((ptype, rule)) = rules.try_next().await? {
+            csv::write_record(&mut writer, &ptype, &rule)?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Reads the rules of a casbin csv policy file from `reader` and stores them in one transaction,
+    /// see [`ImportStrategy`]. Returns the number of rules it stored, the repeated rules of the file count once.
+    /// A line which can not be parsed or stored fails the import before anything is written.
+    /// ```rust,ignore
+    /// let n = adapter.import_csv(BufReader::new(File::open("policy.csv")?), ImportStrategy::Merge).await?;
+    /// ```
+    pub async fn import_csv<R: BufRead>(&self, reader: R, strategy: ImportStrategy) -> Result<u64> {
+        let mut rules = vec![];
+        for (ptype, rule) in csv::read_records(reader)? {
+            rules.extend(self.rule_row(&ptype, &rule)?);
+        }
+        let rules = adapter::distinct_rules(rules);
+        let table = self.write_table()?;
+        match strategy {
+            ImportStrategy::Replace => {
+                let count = rules.len() as u64;
+                match self.save_mode {
+                    SaveMode::Replace => self.committed(adapter::save_policy(self.writer(), &table, rules).await)?,
+                    SaveMode::Diff => self.committed(adapter::save_policy_diff(self.writer(), &table, rules).await).map(|_| ())?,
                 }
+                Ok(count)
+            }
+            ImportStrategy::Merge | ImportStrategy::Fail => {
+                let conflict = match strategy {
+                    ImportStrategy::Fail => ConflictStrategy::Error,
+                    _ => ConflictStrategy::Skip,
+                };
+                let inserted = self.committed(adapter::add_new_policies(self.writer(), &table, rules, conflict).await)?;
+                Ok(inserted.len() as u64)
             }
         }
+    }
 
-        Ok(())
+    /// Read the rules from `replica`, and write them to the pool of [`RbatisAdapter::new`], the primary.
+    /// `load_policy`, `load_filtered_policy`, `stream_policy`, the revision, the change log and the audit log
+    /// are read from the replica, so they lag behind the writes by the replication delay.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&primary).await?.with_replica(&replica);
+    /// ```
+    pub fn with_replica(mut self, replica: &RBatis) -> Self {
+        self.replica = Some(replica.clone());
+        self
     }
 
-    #[cfg_attr(feature = "tracing", instrument(skip(self, m, f), fields(?p = f.p, ?g= f.g), err))]
-    async fn load_filtered_policy<'a>(&mut self, m: &mut dyn Model, f: Filter<'a>) -> Result<()> {
-        let rules = adapter::load_filtered_policy(&self.pool, f).await?;
-        self.is_filtered.store(true, Ordering::SeqCst);
+    /// Read from the primary for `window` after each write of the adapter and its handles, so they read their own writes.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&primary).await?.with_replica(&replica).with_primary_reads_after_write(Duration::from_secs(5));
+    /// ```
+    pub fn with_primary_reads_after_write(mut self, window: Duration) -> Self {
+        self.primary_reads_after_write = Some(window);
+        self
+    }
+
+    /// Choose how `save_policy` writes the rules, the default [`SaveMode::Replace`] rewrites every row.
+    /// With [`SaveMode::Diff`] only the changed rules are inserted or deleted, see [`RbatisAdapter::save_policy_changes`].
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_save_mode(SaveMode::Diff);
+    /// ```
+    pub fn with_save_mode(mut self, save_mode: SaveMode) -> Self {
+        self.save_mode = save_mode;
+        self
+    }
+
+    /// Saves the `p` and `g` rules of the model by writing only the difference to the stored rules,
+    /// in one transaction. Returns the number of rows added and removed.
+    pub async fn save_policy_changes(&self, m: &dyn Model) -> Result<PolicyChanges> {
+        let rules = self.model_rules(m)?;
+        self.committed(adapter::save_policy_diff(self.writer(), &self.write_table()?, rules).await)
+    }
+
+    /// Choose what adding a stored rule does, the default [`ConflictStrategy::Error`] fails.
+    /// The other strategies skip the stored rules with `INSERT IGNORE` (mysql),
+    /// `ON CONFLICT DO NOTHING` (postgres, sqlite) or `MERGE` (mssql).
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_conflict_strategy(ConflictStrategy::Skip);
+    /// ```
+    pub fn with_conflict_strategy(mut self, conflict: ConflictStrategy) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    /// Reject the rules and filters which are skipped by default: an empty rule or ptype, a removed or updated rule
+    /// with more fields than the table has columns, and a `remove_filtered_policy` filter which does not fit the columns.
+    /// They are errors in strict mode, while they are ignored by default like casbin's other adapters do.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Adds the rules in one transaction and returns the ones which were inserted.
+    /// The stored rules are skipped unless the conflict strategy is [`ConflictStrategy::Error`].
+    pub async fn add_new_policies(&self, ptype: &str, rules: Vec<Vec<String>>) -> Result<Vec<Vec<String>>> {
+        let new_rules = self.policy_rows(ptype, &rules)?;
+        let inserted = adapter::add_new_policies(self.writer(), &self.write_table()?, new_rules, self.conflict).await;
+        let inserted = self.committed(inserted)?;
+        Ok(self.inserted_rules(rules, inserted))
+    }
+
+    /// Bump the revision in the `{table_name}_revision` table with every write which changes the rules,
+    /// and log the added and removed rules to the `{table_name}_changes` table, in the same transaction.
+    /// Enable it on every adapter writing the table, so that a `RbatisWatcher` learns about their changes.
+    /// The tables are created by `db_sync`.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_revision_tracking(true);
+    /// ```
+    pub fn with_revision_tracking(mut self, track_revision: bool) -> Self {
+        self.track_revision = track_revision;
+        self
+    }
+
+    /// Record every added and removed rule in the `{table_name}_audit` table, in the transaction of the write.
+    /// An entry holds the operation, the rule, the time and the actor, see [`RbatisAdapter::with_actor`].
+    /// The table is created by `db_sync`.
+    pub fn with_audit(mut self, audit: bool) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// A handle which records `actor` as the author of its writes in the audit log.
+    /// ```rust,ignore
+    /// adapter.with_actor("alice").add_policy("p", "p", to_vec!["bob", "data1", "read"]).await?;
+    /// ```
+    pub fn with_actor(&self, actor: &str) -> Self {
+        let mut this = self.clone();
+        this.actor = Some(actor.to_owned());
+        this
+    }
 
-        for casbin_rule in &rules {
-            if casbin_rule.ptype.is_none() {
+    /// A handle which sees and writes only the rules of tenant `id`, stored in the `tenant` column
+    /// (added by `db_sync`). Every load, insert, update and delete of the handle is scoped to the tenant,
+    /// so the tenants can share the table and the same rule can be stored once per tenant.
+    /// The adapter itself is not scoped, so it works on a table without the `tenant` column. Once `db_sync` or
+    /// `verify_schema` found the column, it is scoped to the empty tenant and does not touch the rules of the handles.
+    /// ```rust,ignore
+    /// let mut e = Enforcer::new("examples/rbac_model.conf", adapter.for_tenant("acme")).await?;
+    /// ```
+    pub fn for_tenant(&self, id: &str) -> Self {
+        let mut this = self.clone();
+        this.tenant = Some(id.to_owned());
+        this.all_tenants = false;
+        // the handle is used by another enforcer
+        this.is_filtered = Arc::new(AtomicBool::new(false));
+        this
+    }
+
+    /// A read-only handle which sees the rules of all tenants, e.g. to export or inspect them.
+    /// Its loads, change log and audit log are not scoped, its writes fail with [`Error::InvalidInput`].
+    /// ```rust,ignore
+    /// let mut rules = adapter.all_tenants().stream_policy();
+    /// ```
+    pub fn all_tenants(&self) -> Self {
+        let mut this = self.clone();
+        this.tenant = None;
+        this.all_tenants = true;
+        this.is_filtered = Arc::new(AtomicBool::new(false));
+        this
+    }
+
+    /// The tenant of a handle created by [`RbatisAdapter::for_tenant`].
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// The writes of the adapter on `tx`, a transaction of the caller on the primary pool, so the rules
+    /// commit or roll back together with the application data written in `tx`. With
+    /// [`RbatisAdapter::with_primary_reads_after_write`], call [`RbatisAdapter::mark_write`] once `tx` has committed.
+    /// ```rust,ignore
+    /// let tx = rb.acquire_begin().await?;
+    /// tx.exec("INSERT INTO users (name) VALUES (?)", vec![to_value!("alice")]).await?;
+    /// adapter.in_transaction(&tx).add_policy("g", "g", to_vec!["alice", "admin"]).await?;
+    /// tx.commit().await?;
+    /// adapter.mark_write();
+    /// ```
+    pub fn in_transaction<'a>(&'a self, tx: &'a dyn Executor) -> TxAdapter<'a> {
+        TxAdapter::new(self, tx)
+    }
+
+    /// A page of at most `limit` audit entries, oldest first. Pass the id of the last entry of a page
+    /// as `after` to get the next one, and 0 for the first one. The entries are scoped to the tenant of the adapter,
+    /// see [`RbatisAdapter::all_tenants`].
+    pub async fn audit_log(&self, after: i64, limit: u64) -> Result<Vec<AuditEntry>> {
+        let dialect = Dialect::of(&self.pool)?;
+        let audit_table = dialect.quote_table(&migrations::audit_table(&self.table_name))?;
+        let tenant = self.table()?.tenant;
+        let records = adapter::load_audit(self.reader(), dialect, &audit_table, tenant.as_deref(), after, limit).await?;
+        Ok(records
+            .into_iter()
+            .map(|x| AuditEntry {
+                id: x.id,
+                operation: x.operation,
+                actor: x.actor,
+                created_at: x.created_at,
+                rule: normalize_policy(&x.rule).unwrap_or_default(),
+                ptype: x.rule.ptype,
+            })
+            .collect())
+    }
+
+    /// The current revision of the rules, see [`RbatisAdapter::with_revision_tracking`].
+    pub async fn revision(&self) -> Result<i64> {
+        self.revision_of(self.reader()).await
+    }
+
+    // the revision stored in the database of `rb`.
+    async fn revision_of(&self, rb: &RBatis) -> Result<i64> {
+        let dialect = Dialect::of(rb)?;
+        let revision_table = dialect.quote_table(&migrations::revision_table(&self.table_name))?;
+        adapter::revision(rb, &revision_table).await
+    }
+
+    /// Applies the rules added and removed after `revision` to a model which was loaded at `revision`,
+    /// and returns the current revision. Only the tracked writes are seen, see [`RbatisAdapter::with_revision_tracking`].
+    /// The model is reloaded completely when the changes are not logged anymore, or all rules were replaced.
+    /// Rebuild the role links of the enforcer afterwards, when the `g` rules changed.
+    /// ```rust,ignore
+    /// let revision = adapter.load_changes_since(revision, e.get_mut_model()).await?;
+    /// e.build_role_links()?;
+    /// ```
+    pub async fn load_changes_since(&self, revision: i64, m: &mut dyn Model) -> Result<i64> {
+        // the revision and the changes are read from the same pool
+        let rb = self.reader();
+        let current = self.revision_of(rb).await?;
+        if revision == current {
+            return Ok(current);
+        }
+        let table = self.table()?;
+        let changes_table = table.dialect.quote_table(&migrations::changes_table(&self.table_name))?;
+        let changes = match revision < current {
+            true => adapter::load_changes(rb, &changes_table, revision, current).await?,
+            false => vec![],
+        };
+        // the revisions are consecutive, and every revision logs at least one change
+        let complete = changes.first().is_some_and(|x| x.revision == revision + 1);
+        // a scoped adapter applies the changes of its tenant only
+        let seen = |x: &ChangeRecord| table.tenant.as_ref().is_none_or(|tenant| &x.tenant == tenant);
+        let reset = changes.iter().any(|x| x.operation == CHANGE_RESET && seen(x));
+        if !complete || reset {
+            m.clear_policy();
+            self.load_pages(rb, &table, m).await?;
+            return Ok(current);
+        }
+
+        for change in changes.iter().filter(|x| seen(x)) {
+            let ptype = &change.rule.ptype;
+            let (Some(sec), Some(rule)) = (ptype.get(..1), normalize_policy(&change.rule)) else {
                 continue;
+            };
+            match change.operation.as_str() {
+                CHANGE_ADD => m.add_policy(sec, ptype, rule),
+                CHANGE_REMOVE => m.remove_policy(sec, ptype, rule),
+                _ => false,
+            };
+        }
+        Ok(current)
+    }
+
+    /// Loads the rules which match `filter` into the model, like `load_filtered_policy` does for casbin's `Filter`,
+    /// but a filter entry can select a single ptype, like `g2`, and match the fields with a [`FieldFilter`](crate::FieldFilter).
+    /// ```rust,ignore
+    /// let filter = PolicyFilter::new().ptype("p", ["alice"]).ptype_where("g2", [(0, FieldFilter::prefix("data"))]);
+    /// adapter.load_policy_where(e.get_mut_model(), &filter).await?;
+    /// ```
+    pub async fn load_policy_where(&self, m: &mut dyn Model, filter: &PolicyFilter) -> Result<()> {
+        let rules = adapter::load_filtered_policy(self.reader(), &self.table()?, filter).await?;
+        self.is_filtered.store(true, Ordering::SeqCst);
+        load_rules(&rules, m);
+        Ok(())
+    }
+
+    /// Deletes the rules which match `filter` in one transaction, returns the number of removed rules.
+    /// ```rust,ignore
+    /// let filter = PolicyFilter::new().ptype_where("p", [(0, FieldFilter::any_of(["alice", "bob"])), (2, FieldFilter::eq("read").not())]);
+    /// adapter.remove_where(&filter).await?;
+    /// ```
+    pub async fn remove_where(&self, filter: &PolicyFilter) -> Result<u64> {
+        self.committed(adapter::remove_where(self.writer(), &self.write_table()?, filter).await)
+    }
+
+    /// Deletes the logged changes up to `revision`, returns the number of deleted changes.
+    /// A model loaded before `revision` is reloaded completely by [`RbatisAdapter::load_changes_since`].
+    pub async fn prune_changes(&self, revision: i64) -> Result<u64> {
+        let dialect = Dialect::of(&self.pool)?;
+        let changes_table = dialect.quote_table(&migrations::changes_table(&self.table_name))?;
+        adapter::prune_changes(self.writer(), &changes_table, revision).await
+    }
+
+    /// Replaces the stored rule `old_rule` with `new_rule` in place, the row keeps its id.
+    /// Returns `false` when `old_rule` is not stored. casbin 2.20 has no update hook in its `Adapter` trait,
+    /// so update the enforcer's model yourself.
+    pub async fn update_policy(&self, sec: &str, ptype: &str, old_rule: Vec<String>, new_rule: Vec<String>) -> Result<bool> {
+        let updated = self.update_policies(sec, ptype, vec![old_rule], vec![new_rule]).await?;
+        Ok(updated > 0)
+    }
+
+    /// Replaces each rule of `old_rules` with the rule of `new_rules` at the same index, in one transaction.
+    /// Returns the number of updated rows, the old rules which are not stored are skipped.
+    pub async fn update_policies(
+        &self,
+        _sec: &str,
+        ptype: &str,
+        old_rules: Vec<Vec<String>>,
+        new_rules: Vec<Vec<String>>,
+    ) -> Result<u64> {
+        let pairs = self.update_pairs(ptype, old_rules, new_rules)?;
+        self.committed(adapter::update_policies(self.writer(), &self.write_table()?, ptype, pairs).await)
+    }
+
+    /// Replaces the rules matching `field_values` from `field_index` on with `new_rules`, in one transaction.
+    /// Returns the replaced rules, an empty value in `field_values` matches any value.
+    pub async fn update_filtered_policies(
+        &self,
+        _sec: &str,
+        ptype: &str,
+        new_rules: Vec<Vec<String>>,
+        field_index: usize,
+        field_values: Vec<String>,
+    ) -> Result<Vec<Vec<String>>> {
+        self.check_filter(field_index, &field_values)?;
+        let new_rules = self.policy_rows(ptype, &new_rules)?;
+        let old_rules =
+            adapter::update_filtered_policies(self.writer(), &self.write_table()?, ptype, new_rules, field_index, field_values).await;
+        let old_rules = self.committed(old_rules)?;
+        Ok(old_rules.iter().filter_map(normalize_policy).collect())
+    }
+
+    /// Synchronize the database schema. It will create the table if not exist,
+    /// and apply the pending migrations, see [`RbatisAdapter::migrate`]. The value columns a raised
+    /// [`RbatisAdapter::with_arity`] needs are added to the existing tables.
+    /// The indexes of [`RbatisAdapter::with_field_indexes`] are created after them.
+    pub async fn db_sync(&self) -> Result<()> {
+        if let Some(field) = self.field_indexes.iter().find(|x| **x >= self.arity) {
+            let err_msg = format!("index on v{field}, the table has {} columns (v0..v{})", self.arity, self.arity - 1);
+            return Err(Error::SchemaMismatch(err_msg).into());
+        }
+        self.migrate().await?;
+        migrations::create_field_indexes(self.writer(), &self.table_name, &self.field_indexes).await?;
+        let columns = migrations::table_columns(&self.pool, Dialect::of(&self.pool)?, &self.table_name).await?;
+        self.tenant_column.store(columns.contains("tenant"), Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Let `db_sync` create an index on each value column `v{field}` of `fields`, like the domain or the subject field.
+    /// The unique key leads with `ptype`, so the queries which filter a value column without the columns before it
+    /// scan the table. An index `idx_{table}_v{field}` over `(v{field}, ptype)` serves the filters on the field in
+    /// `remove_filtered_policy`, `update_filtered_policies`, `load_filtered_policy`, `load_policy_where` and
+    /// `remove_where`, for a ptype or a section. The indexes are created when they are missing, removing a field
+    /// does not drop its index. A field outside of the value columns fails `db_sync`.
+    /// ```rust,ignore
+    /// // the subject and the domain of `p = sub, dom, obj, act`
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_field_indexes([0, 1]);
+    /// adapter.db_sync().await?;
+    /// ```
+    pub fn with_field_indexes(mut self, fields: impl IntoIterator<Item = usize>) -> Self {
+        self.field_indexes.extend(fields);
+        self
+    }
+
+    /// The value columns `db_sync` indexes, see [`RbatisAdapter::with_field_indexes`].
+    pub fn field_indexes(&self) -> Vec<usize> {
+        self.field_indexes.iter().copied().collect()
+    }
+
+    /// The number of value columns the `p*` and `g*` definitions of the model need,
+    /// e.g. 4 for `p = sub, dom, obj, act`.
+    pub fn model_arity(m: &dyn Model) -> usize {
+        ddl::model_arity(m)
+    }
+
+    /// Store the rules of the model in as many value columns as its definitions need, instead of the default 6.
+    /// With `domain_indexes`, `db_sync` also indexes the fields the model uses as domain: the `dom` or `domain`
+    /// field of a policy definition and the third field of a role definition like `g = _, _, _`,
+    /// see [`RbatisAdapter::with_field_indexes`].
+    /// ```rust,ignore
+    /// let m = DefaultModel::from_file("examples/rbac_with_domains_model.conf").await?;
+    /// let adapter = RbatisAdapter::new(&rb).await?.with_model(&m, true);
+    /// adapter.db_sync().await?;
+    /// ```
+    pub fn with_model(mut self, m: &dyn Model, domain_indexes: bool) -> Self {
+        self.arity = ddl::model_arity(m);
+        if domain_indexes {
+            self.field_indexes.extend(ddl::domain_fields(m));
+        }
+        self
+    }
+
+    /// The statements which create the rule table of the model for the current driver, like the scripts
+    /// in `sql` but with the value columns of [`RbatisAdapter::model_arity`], and with `domain_indexes`
+    /// the indexes of [`RbatisAdapter::with_model`]. `db_sync` creates the same table and migrates it.
+    pub fn model_ddl(&self, m: &dyn Model, domain_indexes: bool) -> Result<Vec<String>> {
+        let fields = match domain_indexes {
+            true => ddl::domain_fields(m),
+            false => BTreeSet::new(),
+        };
+        ddl::rule_table_ddl(Dialect::of(&self.pool)?, &self.table_name, ddl::model_arity(m), &fields)
+    }
+
+    /// Applies the pending schema migrations, each one in its own transaction.
+    /// The applied versions are recorded in the `{table_name}_schema_version` table.
+    /// Returns the versions which were applied by this call. The existing tables are widened to the arity
+    /// of the adapter first, when they have fewer value columns.
+    pub async fn migrate(&self) -> Result<Vec<i32>> {
+        migrations::migrate(self.writer(), &self.table_name, self.arity).await
+    }
+
+    /// The current schema version of the table, 0 if it was never migrated.
+    pub async fn schema_version(&self) -> Result<i32> {
+        migrations::schema_version(&self.pool, &self.table_name).await
+    }
+
+    /// Checks that the table has the columns and the unique key the adapter writes, for a table created by another tool.
+    /// Fails with an [`Error::SchemaMismatch`] listing the missing columns, the columns of a wrong type, shorter than
+    /// the lengths of [`RbatisAdapter::with_max_lengths`] or nullable, and a missing unique key over `ptype` and the
+    /// value columns (and `tenant` when the table has it). Only the handles of [`RbatisAdapter::for_tenant`] need the
+    /// `tenant` column.
+    /// ```rust,ignore
+    /// let adapter = RbatisAdapter::new(&rb).await?;
+    /// adapter.verify_schema().await?;
+    /// ```
+    pub async fn verify_schema(&self) -> Result<()> {
+        let tenant_column = schema::verify(&self.pool, &self.table_name, self.arity, self.lengths, self.tenant.is_some()).await?;
+        self.tenant_column.store(tenant_column, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // the rules of `ptype` as rows of the table, the empty rules are skipped.
+    pub(crate) fn policy_rows(&self, ptype: &str, rules: &[Vec<String>]) -> Result<Vec<CasbinRule>> {
+        rules
+            .iter()
+            .map(|x| self.rule_row(ptype, x))
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
+    // the rules of `rules` which are among the `inserted` rows, in the order of `rules`.
+    pub(crate) fn inserted_rules(&self, rules: Vec<Vec<String>>, inserted: Vec<CasbinRule>) -> Vec<Vec<String>> {
+        let mut inserted = inserted.into_iter().map(|x| x.values).collect::<HashSet<_>>();
+        let is_inserted = |rule: &Vec<String>| {
+            let mut values = rule.clone();
+            values.resize(self.arity, String::new());
+            !rule.is_empty() && inserted.remove(&values)
+        };
+        rules.into_iter().filter(is_inserted).collect()
+    }
+
+    // the `(old, new)` pairs of `update_policies`, the pairs with an empty new rule are skipped.
+    pub(crate) fn update_pairs(
+        &self,
+        ptype: &str,
+        old_rules: Vec<Vec<String>>,
+        new_rules: Vec<Vec<String>>,
+    ) -> Result<Vec<(Vec<String>, Vec<String>)>> {
+        if old_rules.len() != new_rules.len() {
+            let err_msg = format!("{} old rules can not be updated to {} new rules", old_rules.len(), new_rules.len());
+            return Err(Error::InvalidInput(err_msg).into());
+        }
+        let mut pairs = vec![];
+        self.check_rules(ptype, &old_rules)?;
+        for (old_rule, new_rule) in old_rules.into_iter().zip(new_rules) {
+            // validates the width of the new rule
+            if self.rule_row(ptype, &new_rule)?.is_some() {
+                pairs.push((old_rule, new_rule));
             }
-            let ptype = casbin_rule.ptype.as_ref().unwrap();
-            if let Some(ref sec) = ptype.chars().next().map(|x| x.to_string()) {
-                if let Some(policy) = normalize_policy(casbin_rule) {
-                    if let Some(t1) = m.get_mut_model().get_mut(sec) {
-                        if let Some(t2) = t1.get_mut(ptype) {
-                            t2.get_mut_policy().insert(policy);
-                        }
-                    }
-                }
+        }
+        Ok(pairs)
+    }
+
+    // the rule as a row of the table, `None` for an empty rule unless the adapter is strict.
+    pub(crate) fn rule_row(&self, ptype: &str, rule: &[String]) -> Result<Option<CasbinRule>> {
+        match save_policy_line(ptype, rule, self.arity, self.lengths)? {
+            None if self.strict => {
+                let err_msg = format!("rule `{}, {}` has no ptype or no values", ptype, rule.join(", "));
+                Err(Error::InvalidInput(err_msg).into())
+            }
+            row => Ok(row),
+        }
+    }
+
+    // fails on the rules which can not be stored when the adapter is strict, they are skipped otherwise.
+    pub(crate) fn check_rules(&self, ptype: &str, rules: &[Vec<String>]) -> Result<()> {
+        if self.strict {
+            for rule in rules {
+                self.rule_row(ptype, rule)?;
             }
         }
+        Ok(())
+    }
+
+    // whether the filter of `remove_filtered_policy` fits the columns of the table, a filter which does not
+    // fit matches no rule, and fails when the adapter is strict.
+    pub(crate) fn fits_filter(&self, field_index: usize, field_values: &[String]) -> Result<bool> {
+        match self.check_filter(field_index, field_values) {
+            Ok(()) => Ok(true),
+            Err(err) if self.strict => Err(err),
+            Err(_) => Ok(false),
+        }
+    }
 
+    // fails unless the filter of `field_values` from `field_index` on fits the columns of the table.
+    pub(crate) fn check_filter(&self, field_index: usize, field_values: &[String]) -> Result<()> {
+        if field_index >= self.arity || field_values.is_empty() || field_values.len() + field_index > self.arity {
+            let err_msg = format!(
+                "the filter of {} values from index {} does not fit the {} columns of the table",
+                field_values.len(),
+                field_index,
+                self.arity
+            );
+            return Err(Error::InvalidInput(err_msg).into());
+        }
         Ok(())
     }
 
-    #[cfg_attr(feature = "tracing", instrument(skip(self, m), err))]
-    async fn save_policy(&mut self, m: &mut dyn Model) -> Result<()> {
+    // the `p` and `g` rules of the model as rows of the table.
+    pub(crate) fn model_rules(&self, m: &dyn Model) -> Result<Vec<CasbinRule>> {
         let mut rules = vec![];
 
         if let Some(ast_map) = m.get_model().get("p") {
             for (ptype, ast) in ast_map {
-                let new_rules = ast.get_policy().into_iter().filter_map(|x| save_policy_line(ptype, x));
+                let new_rules = ast
+                    .get_policy()
+                    .into_iter()
+                    .map(|x| self.rule_row(ptype, x))
+                    .filter_map(Result::transpose);
 
                 rules.extend(new_rules);
             }
@@ -104,19 +731,162 @@ impl Adapter for RbatisAdapter {
 
         if let Some(ast_map) = m.get_model().get("g") {
             for (ptype, ast) in ast_map {
-                let new_rules = ast.get_policy().into_iter().filter_map(|x| save_policy_line(ptype, x));
+                let new_rules = ast
+                    .get_policy()
+                    .into_iter()
+                    .map(|x| self.rule_row(ptype, x))
+                    .filter_map(Result::transpose);
 
                 rules.extend(new_rules);
             }
         }
-        adapter::save_policy(&self.pool, rules).await
+        rules.into_iter().collect()
+    }
+
+    // adds the stored rules to the model page by page.
+    async fn load_pages(&self, rb: &RBatis, table: &RuleTable, m: &mut dyn Model) -> Result<()> {
+        let mut after = None;
+        loop {
+            let page = adapter::load_page(rb, table, after, self.page_size).await?;
+            load_rules(&page, m);
+            match next_page(&page, self.page_size)? {
+                Some(id) => after = Some(id),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // the pool of the writes, it is the primary.
+    fn writer(&self) -> &RBatis {
+        &self.pool
+    }
+
+    // the result of a write, a committed write opens the window of the primary reads after it.
+    fn committed<T>(&self, result: Result<T>) -> Result<T> {
+        if result.is_ok() {
+            self.mark_write();
+        }
+        result
+    }
+
+    /// Opens the window of [`RbatisAdapter::with_primary_reads_after_write`], for a write committed outside
+    /// of the adapter, like the writes of [`RbatisAdapter::in_transaction`]. The writes of the adapter open it
+    /// once they have committed.
+    pub fn mark_write(&self) {
+        if self.replica.is_some() && self.primary_reads_after_write.is_some() {
+            *self.last_write.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    // the pool of the reads, the replica unless the primary reads after a write are due.
+    fn reader(&self) -> &RBatis {
+        let Some(replica) = &self.replica else {
+            return &self.pool;
+        };
+        let last_write = *self.last_write.lock().unwrap();
+        match (self.primary_reads_after_write, last_write) {
+            (Some(window), Some(at)) if at.elapsed() < window => &self.pool,
+            _ => replica,
+        }
+    }
+
+    // the rule table for the current driver.
+    pub(crate) fn table(&self) -> Result<RuleTable> {
+        let dialect = Dialect::of(&self.pool)?;
+        Ok(RuleTable {
+            dialect,
+            name: dialect.quote_table(&self.table_name)?,
+            arity: self.arity,
+            batch_size: self.batch_size,
+            tracking: match self.track_revision {
+                true => Some(Tracking {
+                    revision_table: dialect.quote_table(&migrations::revision_table(&self.table_name))?,
+                    changes_table: dialect.quote_table(&migrations::changes_table(&self.table_name))?,
+                }),
+                false => None,
+            },
+            audit: match self.audit {
+                true => Some(Audit {
+                    table: dialect.quote_table(&migrations::audit_table(&self.table_name))?,
+                    actor: self.actor.clone(),
+                }),
+                false => None,
+            },
+            tenant: match (&self.tenant, self.all_tenants) {
+                (Some(tenant), _) => Some(tenant.clone()),
+                // the adapter itself is scoped to the empty tenant its rules are written with, once the table has it
+                (None, false) if self.tenant_column.load(Ordering::SeqCst) => Some(String::new()),
+                _ => None,
+            },
+        })
+    }
+
+    // the rule table of a write, the handle of all tenants is read-only.
+    pub(crate) fn write_table(&self) -> Result<RuleTable> {
+        if self.all_tenants {
+            let err_msg = "the handle of all tenants is read-only, write with the adapter or a tenant handle".to_owned();
+            return Err(Error::InvalidInput(err_msg).into());
+        }
+        self.table()
+    }
+}
+
+// the id to read the page after `page` from, `None` after the last page.
+fn next_page(page: &[CasbinRule], page_size: usize) -> Result<Option<i32>> {
+    match page.last() {
+        Some(rule) if page.len() >= page_size => match rule.id {
+            Some(id) => Ok(Some(id)),
+            // the next page would start over from the first one
+            None => Err(Error::SchemaMismatch("a rule without id can not be read in pages".to_owned()).into()),
+        },
+        _ => Ok(None),
+    }
+}
+
+// adds the rules to the sections of the model they belong to.
+fn load_rules(rules: &[CasbinRule], m: &mut dyn Model) {
+    for casbin_rule in rules {
+        let ptype = &casbin_rule.ptype;
+        if let Some(ref sec) = ptype.chars().next().map(|x| x.to_string()) {
+            if let Some(rule) = normalize_policy(casbin_rule) {
+                if let Some(t1) = m.get_mut_model().get_mut(sec) {
+                    if let Some(t2) = t1.get_mut(ptype) {
+                        t2.get_mut_policy().insert(rule);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Adapter for RbatisAdapter {
+    #[cfg_attr(feature = "tracing", instrument(skip(self, m), err))]
+    async fn load_policy(&mut self, m: &mut dyn Model) -> Result<()> {
+        self.load_pages(self.reader(), &self.table()?, m).await
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip(self, m, f), fields(?p = f.p, ?g= f.g), err))]
+    async fn load_filtered_policy<'a>(&mut self, m: &mut dyn Model, f: Filter<'a>) -> Result<()> {
+        self.load_policy_where(m, &PolicyFilter::from(f)).await
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip(self, m), err))]
+    async fn save_policy(&mut self, m: &mut dyn Model) -> Result<()> {
+        match self.save_mode {
+            SaveMode::Replace => {
+                let result = adapter::save_policy(self.writer(), &self.write_table()?, self.model_rules(m)?).await;
+                self.committed(result)
+            }
+            SaveMode::Diff => self.save_policy_changes(m).await.map(|_| ()),
+        }
     }
 
     #[cfg_attr(feature = "tracing", instrument(skip(self), err, ret))]
     async fn add_policy(&mut self, _sec: &str, ptype: &str, rule: Vec<String>) -> Result<bool> {
-        if let Some(new_rule) = save_policy_line(ptype, rule.as_slice()) {
-            let result = adapter::add_policy(&self.pool, new_rule).await;
-            return result;
+        if let Some(new_rule) = self.rule_row(ptype, &rule)? {
+            let result = adapter::add_policy(self.writer(), &self.write_table()?, new_rule, self.conflict).await;
+            return self.committed(result);
         }
 
         Ok(false)
@@ -124,22 +894,21 @@ impl Adapter for RbatisAdapter {
 
     #[cfg_attr(feature = "tracing", instrument(skip(self), err, ret))]
     async fn add_policies(&mut self, _sec: &str, ptype: &str, rules: Vec<Vec<String>>) -> Result<bool> {
-        let new_rules = rules
-            .iter()
-            .filter_map(|x| save_policy_line(ptype, x))
-            .collect::<Vec<CasbinRule>>();
-
-        adapter::add_policies(&self.pool, new_rules).await
+        let new_rules = self.policy_rows(ptype, &rules)?;
+        let result = adapter::add_policies(self.writer(), &self.write_table()?, new_rules, self.conflict).await;
+        self.committed(result)
     }
 
     #[cfg_attr(feature = "tracing", instrument(skip(self), err, ret))]
     async fn remove_policy(&mut self, _sec: &str, pt: &str, rule: Vec<String>) -> Result<bool> {
-        adapter::remove_policy(&self.pool, pt, rule).await
+        self.check_rules(pt, std::slice::from_ref(&rule))?;
+        self.committed(adapter::remove_policy(self.writer(), &self.write_table()?, pt, rule).await)
     }
 
     #[cfg_attr(feature = "tracing", instrument(skip(self), err, ret))]
     async fn remove_policies(&mut self, _sec: &str, pt: &str, rules: Vec<Vec<String>>) -> Result<bool> {
-        adapter::remove_policies(&self.pool, pt, rules).await
+        self.check_rules(pt, &rules)?;
+        self.committed(adapter::remove_policies(self.writer(), &self.write_table()?, pt, rules).await)
     }
     #[cfg_attr(feature = "tracing", instrument(skip(self), err, ret))]
     async fn remove_filtered_policy(
@@ -149,8 +918,9 @@ impl Adapter for RbatisAdapter {
         field_index: usize,
         field_values: Vec<String>,
     ) -> Result<bool> {
-        if field_index <= 5 && !field_values.is_empty() && field_values.len() + field_index <= 6 {
-            adapter::remove_filtered_policy(&self.pool, pt, field_index, field_values).await
+        if self.fits_filter(field_index, &field_values)? {
+            let result = adapter::remove_filtered_policy(self.writer(), &self.write_table()?, pt, field_index, field_values).await;
+            self.committed(result)
         } else {
             Ok(false)
         }
@@ -158,7 +928,7 @@ impl Adapter for RbatisAdapter {
 
     #[cfg_attr(feature = "tracing", instrument(skip(self), err))]
     async fn clear_policy(&mut self) -> Result<()> {
-        adapter::clear_policy(&self.pool).await
+        self.committed(adapter::clear_policy(self.writer(), &self.write_table()?).await)
     }
 
     #[cfg_attr(feature = "tracing", instrument(skip(self)))]
@@ -173,6 +943,872 @@ mod tests {
     use crate::to_vec;
     use rbdc_mysql::driver::MysqlDriver;
 
+    // in-memory sqlite, one connection so that every query sees the same database.
+    async fn sqlite_rb() -> RBatis {
+        crate::testing::sqlite_rbatis().await
+    }
+
+    #[tokio::test]
+    async fn test_table_name() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut svc_a = RbatisAdapter::new(&rb).await.unwrap().with_table_name("svc_a_casbin_rule");
+        let mut svc_b = RbatisAdapter::new(&rb).await.unwrap().with_table_name("svc_b \"casbin\" rule");
+        assert_eq!(svc_a.table_name(), "svc_a_casbin_rule");
+        svc_a.db_sync().await.unwrap();
+        svc_b.db_sync().await.unwrap();
+        // db_sync is idempotent
+        svc_a.db_sync().await.unwrap();
+
+        assert!(svc_a.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap());
+        assert!(svc_b.add_policy("", "p", to_vec!["bob", "data2", "write"]).await.unwrap());
+        assert!(svc_b.add_policy("", "g", to_vec!["bob", "admin"]).await.unwrap());
+
+        let m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        let e = Enforcer::new(m, svc_a.clone()).await.unwrap();
+        assert_eq!(e.get_policy(), vec![to_vec!["alice", "data1", "read"]]);
+        assert!(e.get_grouping_policy().is_empty());
+
+        let m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        let e = Enforcer::new(m, svc_b.clone()).await.unwrap();
+        assert_eq!(e.get_policy(), vec![to_vec!["bob", "data2", "write"]]);
+        assert_eq!(e.get_grouping_policy(), vec![to_vec!["bob", "admin"]]);
+
+        assert!(svc_b.remove_filtered_policy("", "g", 0, to_vec!["bob"]).await.unwrap());
+        svc_a.clear_policy().await.unwrap();
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from svc_a_casbin_rule", vec![]).await.unwrap();
+        assert!(rules.is_empty());
+        let rules: Vec<CasbinRule> = rb
+            .query_decode("select * from \"svc_b \"\"casbin\"\" rule\"", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rules.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_arity() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_arity(8);
+        adapter.db_sync().await.unwrap();
+
+        let wide = to_vec!["alice", "data1", "read", "allow", "", "t1", "t2", "t3"];
+        assert!(adapter.add_policy("", "p", wide.clone()).await.unwrap());
+        assert!(adapter.add_policy("", "p", to_vec!["bob", "data2", "write"]).await.unwrap());
+        // wider than the table, it is not truncated
+        let err = adapter
+            .add_policy("", "p", to_vec!["a", "b", "c", "d", "e", "f", "g", "h", "i"])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("at most 8"));
+        assert!(!adapter.remove_policy("", "p", to_vec!["a", "b", "c", "d", "e", "f", "g", "h", "i"]).await.unwrap());
+
+        let mut m = DefaultModel::default();
+        m.add_def("p", "p", "sub, obj, act, eft, a, b, c, d");
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), vec![wide, to_vec!["bob", "data2", "write"]]);
+
+        assert!(!adapter.remove_filtered_policy("", "p", 7, to_vec!["t4"]).await.unwrap());
+        assert!(adapter.remove_filtered_policy("", "p", 6, to_vec!["t2", "t3"]).await.unwrap());
+        assert!(adapter.remove_policy("", "p", to_vec!["bob", "data2", "write"]).await.unwrap());
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        assert!(rules.is_empty());
+
+        // the rules which only differ after a long shared prefix are distinct
+        let prefix = "x".repeat(127);
+        let rules = (0..8).map(|i| vec![format!("{prefix}a"); i + 1]).collect::<Vec<_>>();
+        let mut other = rules.clone();
+        other.iter_mut().for_each(|x| x.last_mut().unwrap().replace_range(127.., "b"));
+        assert!(adapter.add_policies("", "p", rules).await.unwrap());
+        assert!(adapter.add_policies("", "p", other).await.unwrap());
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(rules.len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_batch_insert() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_batch_size(300);
+        adapter.db_sync().await.unwrap();
+
+        let rules = (0..1000).map(|i| to_vec![format!("user{i}"), "data1", "read"]).collect::<Vec<_>>();
+        assert!(adapter.add_policies("", "p", rules.clone()).await.unwrap());
+        let count: i64 = rb.query_decode("select count(*) as n from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(count, 1000);
+
+        // a duplicate rolls back the whole batch
+        let mut duplicated = (1000..1500).map(|i| to_vec![format!("user{i}"), "data1", "read"]).collect::<Vec<_>>();
+        duplicated.push(to_vec!["user0", "data1", "read"]);
+        assert!(adapter.add_policies("", "p", duplicated).await.is_err());
+        let count: i64 = rb.query_decode("select count(*) as n from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(count, 1000);
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), rules);
+    }
+
+    #[tokio::test]
+    async fn test_save_policy_replace() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap();
+        adapter.db_sync().await.unwrap();
+        let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv").await.unwrap();
+
+        assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+        // the stored rules are replaced, not appended
+        assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+        e.get_mut_model().add_policy("p", "p", to_vec!["carol", "data3", "read"]);
+        assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+        let saved: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(saved.len(), 6);
+
+        // a failed save keeps the previous rules
+        rb.exec(
+            "create trigger boom before insert on casbin_rule when new.v0 = 'boom' begin select raise(abort, 'boom'); end",
+            vec![],
+        )
+        .await
+        .unwrap();
+        e.get_mut_model().add_policy("p", "p", to_vec!["boom", "data3", "read"]);
+        assert!(adapter.save_policy(e.get_mut_model()).await.is_err());
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(rules, saved);
+    }
+
+    #[tokio::test]
+    async fn test_save_policy_diff() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_save_mode(SaveMode::Diff);
+        adapter.db_sync().await.unwrap();
+        let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv").await.unwrap();
+
+        let changes = adapter.save_policy_changes(e.get_model()).await.unwrap();
+        assert_eq!(changes, PolicyChanges { added: 5, removed: 0 });
+        let ids: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+
+        let changes = adapter.save_policy_changes(e.get_model()).await.unwrap();
+        assert_eq!(changes, PolicyChanges { added: 0, removed: 0 });
+
+        e.get_mut_model().remove_policy("p", "p", to_vec!["bob", "data2", "write"]);
+        e.get_mut_model().add_policy("p", "p", to_vec!["carol", "data3", "read"]);
+        let changes = adapter.save_policy_changes(e.get_model()).await.unwrap();
+        assert_eq!(changes, PolicyChanges { added: 1, removed: 1 });
+        assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+        // the unchanged rows keep their ids
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(rules.len(), 5);
+        let kept = ids.into_iter().filter(|x| x.values[0] != "bob").collect::<Vec<_>>();
+        assert_eq!(rules[..4], kept[..]);
+        assert_eq!(rules[4].values[..3], to_vec!["carol", "data3", "read"]);
+    }
+
+    #[tokio::test]
+    async fn test_conflict_strategy() {
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap();
+        adapter.db_sync().await.unwrap();
+        assert!(adapter.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap());
+        let err = adapter.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap_err();
+        assert!(Error::downcast(&err).is_some_and(Error::is_duplicate));
+
+        let rules = vec![
+            to_vec!["alice", "data1", "read"],
+            to_vec!["bob", "data2", "write"],
+            to_vec!["bob", "data2", "write"],
+        ];
+        let mut adapter = adapter.with_conflict_strategy(ConflictStrategy::Skip);
+        assert!(adapter.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap());
+        assert!(adapter.add_policies("", "p", rules.clone()).await.unwrap());
+        let count: i64 = rb.query_decode("select count(*) as n from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(count, 2);
+
+        let mut adapter = adapter.with_conflict_strategy(ConflictStrategy::Report);
+        assert!(!adapter.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap());
+        assert!(!adapter.add_policies("", "p", rules.clone()).await.unwrap());
+        assert!(adapter.add_policies("", "g", vec![to_vec!["alice", "admin"]]).await.unwrap());
+
+        let mut rules = rules;
+        rules.push(to_vec!["carol", "data3", "read"]);
+        let inserted = adapter.add_new_policies("p", rules).await.unwrap();
+        assert_eq!(inserted, vec![to_vec!["carol", "data3", "read"]]);
+        let count: i64 = rb.query_decode("select count(*) as n from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(count, 4);
+
+        let adapter = adapter.with_conflict_strategy(ConflictStrategy::Error);
+        assert!(adapter.add_new_policies("p", vec![to_vec!["carol", "data3", "read"]]).await.is_err());
+
+        // the stored rules are told apart chunk by chunk
+        let adapter = adapter.with_conflict_strategy(ConflictStrategy::Skip).with_batch_size(120);
+        let rules = (0..300).map(|i| to_vec![format!("user_{i}"), "data1", "read"]).collect::<Vec<_>>();
+        let stored = rules.iter().step_by(3).cloned().collect::<Vec<_>>();
+        assert_eq!(adapter.add_new_policies("p", stored).await.unwrap().len(), 100);
+        let inserted = adapter.add_new_policies("p", rules.clone()).await.unwrap();
+        assert_eq!(inserted.len(), 200);
+        assert!(inserted.iter().all(|x| rules.iter().position(|y| y == x).unwrap() % 3 != 0));
+    }
+
+    #[tokio::test]
+    async fn test_update_policy() {
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap();
+        adapter.db_sync().await.unwrap();
+        let rules = vec![
+            to_vec!["alice", "data1", "read"],
+            to_vec!["bob", "data2", "write"],
+            to_vec!["carol", "data2", "read"],
+        ];
+        adapter.add_policies("", "p", rules).await.unwrap();
+        let before: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+
+        assert!(adapter
+            .update_policy("p", "p", to_vec!["alice", "data1", "read"], to_vec!["alice", "data1", "write"])
+            .await
+            .unwrap());
+        assert!(!adapter
+            .update_policy("p", "p", to_vec!["nobody", "data1", "read"], to_vec!["alice", "data3", "write"])
+            .await
+            .unwrap());
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(rules[0].id, before[0].id);
+        assert_eq!(rules[0].values[..3], to_vec!["alice", "data1", "write"]);
+
+        // one failing pair rolls back the whole update
+        let old_rules = vec![to_vec!["bob", "data2", "write"], to_vec!["carol", "data2", "read"]];
+        let new_rules = vec![to_vec!["bob", "data3", "write"], to_vec!["alice", "data1", "write"]];
+        assert!(adapter.update_policies("p", "p", old_rules.clone(), new_rules).await.is_err());
+        assert!(adapter.update_policies("p", "p", old_rules.clone(), vec![]).await.is_err());
+        let new_rules = vec![to_vec!["bob", "data3", "write"], to_vec!["carol", "data3", "read"]];
+        assert_eq!(adapter.update_policies("p", "p", old_rules, new_rules).await.unwrap(), 2);
+
+        let replaced = adapter
+            .update_filtered_policies("p", "p", vec![to_vec!["dave", "data3", "read"]], 1, to_vec!["data3"])
+            .await
+            .unwrap();
+        assert_eq!(replaced, vec![to_vec!["bob", "data3", "write"], to_vec!["carol", "data3", "read"]]);
+        assert!(adapter
+            .update_filtered_policies("p", "p", vec![], 6, to_vec!["data3"])
+            .await
+            .is_err());
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        let rules = rules.iter().filter_map(normalize_policy).collect::<Vec<_>>();
+        assert_eq!(rules, vec![to_vec!["alice", "data1", "write"], to_vec!["dave", "data3", "read"]]);
+    }
+
+    #[tokio::test]
+    async fn test_revision_tracking() {
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_revision_tracking(true);
+        adapter.db_sync().await.unwrap();
+        assert_eq!(adapter.revision().await.unwrap(), 0);
+
+        adapter.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap();
+        adapter.add_policies("", "p", vec![to_vec!["bob", "data2", "write"]]).await.unwrap();
+        assert_eq!(adapter.revision().await.unwrap(), 2);
+        // no change, no new revision
+        assert!(!adapter.remove_policy("", "p", to_vec!["carol", "data3", "read"]).await.unwrap());
+        assert!(adapter.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.is_err());
+        assert_eq!(adapter.revision().await.unwrap(), 2);
+
+        assert!(adapter.remove_filtered_policy("", "p", 0, to_vec!["bob"]).await.unwrap());
+        adapter.clear_policy().await.unwrap();
+        assert_eq!(adapter.revision().await.unwrap(), 4);
+
+        // untracked writes leave the revision alone
+        let mut untracked = adapter.clone().with_revision_tracking(false);
+        untracked.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap();
+        assert_eq!(adapter.revision().await.unwrap(), 4);
+
+        // the skipped rules are not logged as added
+        let mut skip = adapter.clone().with_conflict_strategy(ConflictStrategy::Skip);
+        assert!(skip.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap());
+        assert_eq!(adapter.revision().await.unwrap(), 4);
+        let rules = vec![to_vec!["alice", "data1", "read"], to_vec!["carol", "data3", "read"]];
+        assert!(skip.add_policies("", "p", rules).await.unwrap());
+        assert_eq!(adapter.revision().await.unwrap(), 5);
+        let logged: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule_changes where revision = 5", vec![]).await.unwrap();
+        assert_eq!(logged.iter().map(|x| x.values[0].as_str()).collect::<Vec<_>>(), vec!["carol"]);
+    }
+
+    #[tokio::test]
+    async fn test_load_changes_since() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut writer = RbatisAdapter::new(&rb).await.unwrap().with_revision_tracking(true);
+        writer.db_sync().await.unwrap();
+        writer.add_policies("", "p", vec![to_vec!["alice", "data1", "read"], to_vec!["bob", "data2", "write"]]).await.unwrap();
+
+        let mut reader = writer.clone();
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        reader.load_policy(&mut m).await.unwrap();
+        let revision = reader.revision().await.unwrap();
+        assert_eq!(reader.load_changes_since(revision, &mut m).await.unwrap(), revision);
+
+        writer.remove_policy("", "p", to_vec!["bob", "data2", "write"]).await.unwrap();
+        writer.add_policy("", "g", to_vec!["alice", "admin"]).await.unwrap();
+        writer
+            .update_policy("p", "p", to_vec!["alice", "data1", "read"], to_vec!["alice", "data1", "write"])
+            .await
+            .unwrap();
+        writer.remove_filtered_policy("", "g", 1, to_vec!["admin"]).await.unwrap();
+        writer.add_policy("", "g", to_vec!["bob", "admin"]).await.unwrap();
+        // a change made without tracking is not seen
+        writer.clone().with_revision_tracking(false).add_policy("", "p", to_vec!["untracked", "data", "read"]).await.unwrap();
+
+        let revision = reader.load_changes_since(revision, &mut m).await.unwrap();
+        assert_eq!(revision, 6);
+        assert_eq!(m.get_policy("p", "p"), vec![to_vec!["alice", "data1", "write"]]);
+        assert_eq!(m.get_policy("g", "g"), vec![to_vec!["bob", "admin"]]);
+
+        // a replace, or pruned changes, load all rules
+        let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv").await.unwrap();
+        writer.save_policy(e.get_mut_model()).await.unwrap();
+        assert_eq!(reader.load_changes_since(revision, &mut m).await.unwrap(), 7);
+        assert_eq!(m.get_policy("p", "p"), e.get_policy());
+        assert_eq!(m.get_policy("g", "g"), e.get_grouping_policy());
+
+        writer.remove_policy("", "g", to_vec!["alice", "data2_admin"]).await.unwrap();
+        assert_eq!(writer.prune_changes(8).await.unwrap(), 10);
+        let mut stale = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        assert_eq!(reader.load_changes_since(5, &mut stale).await.unwrap(), 8);
+        assert!(stale.get_policy("g", "g").is_empty());
+        assert_eq!(stale.get_policy("p", "p"), e.get_policy());
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_audit(true);
+        adapter.db_sync().await.unwrap();
+
+        let mut alice = adapter.with_actor("alice").with_conflict_strategy(ConflictStrategy::Skip);
+        alice.add_policies("", "p", vec![to_vec!["bob", "data1", "read"], to_vec!["bob", "data2", "read"]]).await.unwrap();
+        // a skipped rule is not audited
+        alice.add_policies("", "p", vec![to_vec!["bob", "data1", "read"], to_vec!["bob", "data3", "read"]]).await.unwrap();
+        adapter.remove_filtered_policy("", "p", 1, to_vec!["data2"]).await.unwrap();
+        assert!(!adapter.remove_policy("", "p", to_vec!["nobody", "data1", "read"]).await.unwrap());
+        let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv").await.unwrap();
+        adapter.with_actor("admin").save_policy(e.get_mut_model()).await.unwrap();
+
+        let page = adapter.audit_log(0, 4).await.unwrap();
+        let summary = page
+            .iter()
+            .map(|x| (x.operation.as_str(), x.actor.as_deref(), x.rule.join(", ")))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("add", Some("alice"), "bob, data1, read".to_owned()),
+                ("add", Some("alice"), "bob, data2, read".to_owned()),
+                ("add", Some("alice"), "bob, data3, read".to_owned()),
+                ("remove", None, "bob, data2, read".to_owned()),
+            ]
+        );
+        assert_eq!(page[0].ptype, "p");
+        assert!(page[0].created_at.unix_timestamp() > 0);
+
+        let page = adapter.audit_log(page[3].id, 100).await.unwrap();
+        assert_eq!(page.len(), 6);
+        assert_eq!((page[0].operation.as_str(), page[0].actor.as_deref()), ("clear", Some("admin")));
+        assert!(page[1..].iter().all(|x| x.operation == "add"));
+        assert!(adapter.audit_log(page[5].id, 100).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_load_filtered_policy() {
+        use casbin::prelude::*;
+        let model = "
+            [request_definition]
+            r = sub, obj, act
+            [policy_definition]
+            p = sub, obj, act
+            [role_definition]
+            g = _, _
+            g2 = _, _
+            [policy_effect]
+            e = some(where (p.eft == allow))
+            [matchers]
+            m = g(r.sub, p.sub) && g2(r.obj, p.obj) && r.act == p.act";
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap();
+        adapter.db_sync().await.unwrap();
+        adapter.add_policies("p", "p", vec![to_vec!["admin", "data_group", "read"], to_vec!["bob", "data2", "write"]]).await.unwrap();
+        adapter.add_policies("g", "g", vec![to_vec!["alice", "admin"], to_vec!["bob", "bob"]]).await.unwrap();
+        adapter.add_policies("g", "g2", vec![to_vec!["data1", "data_group"], to_vec!["data2", "data2"]]).await.unwrap();
+
+        let m = DefaultModel::from_str(model).await.unwrap();
+        let mut e = Enforcer::new(m, adapter.clone()).await.unwrap();
+        let filter = Filter {
+            p: vec!["admin"],
+            g: vec![],
+        };
+        e.load_filtered_policy(filter).await.unwrap();
+        assert!(e.is_filtered());
+        // the g2 rules are loaded with the g section
+        assert_eq!(e.get_named_grouping_policy("g2").len(), 2);
+        assert!(e.enforce(("alice", "data1", "read")).unwrap());
+        assert!(!e.enforce(("bob", "data2", "write")).unwrap());
+
+        let filter = PolicyFilter::new().ptype("p", ["bob"]).ptype("g", ["bob"]).ptype("g2", ["data2"]);
+        e.get_mut_model().clear_policy();
+        adapter.load_policy_where(e.get_mut_model(), &filter).await.unwrap();
+        e.build_role_links().unwrap();
+        assert_eq!(e.get_named_grouping_policy("g2"), vec![to_vec!["data2", "data2"]]);
+        assert!(e.enforce(("bob", "data2", "write")).unwrap());
+        assert!(!e.enforce(("alice", "data1", "read")).unwrap());
+
+        // the wildcards of a section are escaped
+        e.get_mut_model().clear_policy();
+        adapter.load_policy_where(e.get_mut_model(), &PolicyFilter::new().section("_", [""])).await.unwrap();
+        assert!(e.get_policy().is_empty());
+        assert!(adapter.load_policy_where(e.get_mut_model(), &PolicyFilter::new()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_remove_where() {
+        use crate::filter::FieldFilter;
+        let rb = sqlite_rb().await;
+        let adapter = RbatisAdapter::new(&rb).await.unwrap().with_audit(true);
+        adapter.db_sync().await.unwrap();
+        let rules = vec![
+            to_vec!["user_1", "data1", "read"],
+            to_vec!["user_2", "data1", "write"],
+            to_vec!["user%", "data2", "read"],
+            to_vec!["admin", "data2", "write"],
+        ];
+        adapter.add_new_policies("p", rules).await.unwrap();
+        adapter.add_new_policies("g", vec![to_vec!["user_1", "admin"]]).await.unwrap();
+
+        let table = adapter.table().unwrap();
+        let filter = PolicyFilter::new().ptype_where("p", [(0, FieldFilter::prefix("user%"))]);
+        let loaded = adapter::load_filtered_policy(&rb, &table, &filter).await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        let filter = PolicyFilter::new().section_where("p", [(0, FieldFilter::like("user_%")), (2, FieldFilter::eq("read").not())]);
+        let loaded = adapter::load_filtered_policy(&rb, &table, &filter).await.unwrap();
+        assert_eq!(loaded.iter().map(|x| x.values[0].as_str()).collect::<Vec<_>>(), vec!["user_2"]);
+        let filter = PolicyFilter::new().section_where("p", [(0, FieldFilter::like("user!%"))]);
+        let loaded = adapter::load_filtered_policy(&rb, &table, &filter).await.unwrap();
+        assert_eq!(loaded.iter().map(|x| x.values[0].as_str()).collect::<Vec<_>>(), vec!["user%"]);
+
+        let filter = PolicyFilter::new()
+            .ptype_where("p", [(0, FieldFilter::any_of(["user_1", "admin"])), (1, FieldFilter::eq("data1").not())])
+            .ptype("g", ["user_1"]);
+        assert_eq!(adapter.remove_where(&filter).await.unwrap(), 2);
+        let stored = adapter::load_page(&rb, &table, None, 100).await.unwrap();
+        assert_eq!(stored.len(), 3);
+        assert!(stored.iter().all(|x| x.ptype == "p" && x.values[0] != "admin"));
+        let removed = adapter.audit_log(0, 100).await.unwrap().into_iter().filter(|x| x.operation == "remove").count();
+        assert_eq!(removed, 2);
+        assert_eq!(adapter.remove_where(&PolicyFilter::new()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_load_pages() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_page_size(7);
+        adapter.db_sync().await.unwrap();
+        let rules = (0..50).map(|i| to_vec![format!("user{i}"), "data1", "read"]).collect::<Vec<_>>();
+        adapter.add_policies("p", "p", rules.clone()).await.unwrap();
+        adapter.add_policies("g", "g", vec![to_vec!["user0", "admin"]]).await.unwrap();
+        // the ids have a gap
+        adapter.remove_policy("p", "p", rules[20].clone()).await.unwrap();
+
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p").len(), 49);
+        assert_eq!(m.get_policy("g", "g").len(), 1);
+
+        let streamed = adapter.stream_policy().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(streamed.len(), 50);
+        assert_eq!(streamed[0], ("p".to_owned(), rules[0].clone()));
+        assert_eq!(streamed[49], ("g".to_owned(), to_vec!["user0", "admin"]));
+        // the last page is full
+        let streamed = adapter.with_page_size(10).stream_policy().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(streamed.len(), 50);
+
+        // a full page without ids ends the reads
+        let page = vec![CasbinRule::default(); 2];
+        assert!(next_page(&page, 2).is_err());
+        assert_eq!(next_page(&page, 3).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_replica() {
+        use casbin::prelude::*;
+        let primary = sqlite_rb().await;
+        let replica = sqlite_rb().await;
+        RbatisAdapter::new(&replica).await.unwrap().db_sync().await.unwrap();
+        let mut adapter = RbatisAdapter::new(&primary).await.unwrap().with_replica(&replica);
+        adapter.db_sync().await.unwrap();
+        adapter.add_policy("p", "p", to_vec!["alice", "data1", "read"]).await.unwrap();
+
+        // the replica has not caught up
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+
+        let mut adapter = adapter.with_primary_reads_after_write(Duration::from_secs(60));
+        let handle = adapter.with_actor("alice");
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+        handle.update_policy("p", "p", to_vec!["alice", "data1", "read"], to_vec!["alice", "data1", "write"]).await.unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), vec![to_vec!["alice", "data1", "write"]]);
+
+        // the window has passed
+        adapter.primary_reads_after_write = Some(Duration::ZERO);
+        let mut m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+
+        // a failed write does not read from the primary
+        adapter.primary_reads_after_write = Some(Duration::from_secs(60));
+        *adapter.last_write.lock().unwrap() = None;
+        assert!(adapter.add_policy("p", "p", to_vec!["alice", "data1", "write"]).await.is_err());
+        assert!(adapter.last_write.lock().unwrap().is_none());
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+
+        // the writes of a transaction read from the primary once it has committed
+        let tx = primary.acquire_begin().await.unwrap();
+        adapter.in_transaction(&tx).add_policy("p", "p", to_vec!["bob", "data2", "read"]).await.unwrap();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+        tx.commit().await.unwrap();
+        drop(tx);
+        adapter.mark_write();
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let rb = sqlite_rb().await;
+        // a table created before the migrations existed
+        rb.exec(include_str!("../sql/sqlite.sql"), vec![]).await.unwrap();
+        // and the table of a failed rebuild
+        rb.exec("create table casbin_rule_rebuild (id integer)", vec![]).await.unwrap();
+        rb.exec("insert into casbin_rule (ptype, v0, v1, v2, v3, v4, v5) values ('p', 'alice', 'data1', 'read', '', '', '')", vec![])
+            .await
+            .unwrap();
+
+        let adapter = RbatisAdapter::new(&rb).await.unwrap();
+        assert_eq!(adapter.schema_version().await.unwrap(), 0);
+        let applied = adapter.migrate().await.unwrap();
+        assert_eq!(applied, (1..=migrations::MIGRATIONS.len() as i32).collect::<Vec<_>>());
+        assert_eq!(adapter.schema_version().await.unwrap(), migrations::MIGRATIONS.len() as i32);
+        assert!(adapter.migrate().await.unwrap().is_empty());
+        adapter.db_sync().await.unwrap();
+
+        let rules: Vec<CasbinRule> = rb.query_decode("select * from casbin_rule", vec![]).await.unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].id, Some(1));
+        // the rule table is rebuilt with the tenant column and its unique key
+        let unique = "insert into casbin_rule (tenant, ptype, v0, v1, v2, v3, v4, v5) values (?, 'p', 'alice', 'data1', 'read', '', '', '')";
+        rb.exec(unique, vec!["acme".into()]).await.unwrap();
+        assert!(rb.exec(unique, vec!["acme".into()]).await.is_err());
+        assert!(rb.exec(unique, vec!["".into()]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_raise_arity() {
+        let rb = sqlite_rb().await;
+        let mut narrow = RbatisAdapter::new(&rb).await.unwrap().with_arity(3).with_revision_tracking(true).with_audit(true);
+        narrow.db_sync().await.unwrap();
+        assert!(narrow.add_policy("p", "p", to_vec!["alice", "data1", "read"]).await.unwrap());
+
+        let mut wide = narrow.clone().with_arity(5).with_field_indexes([4]);
+        wide.db_sync().await.unwrap();
+        wide.verify_schema().await.unwrap();
+        assert_eq!(wide.field_indexes(), vec![4]);
+        // the unique key covers the new columns
+        assert!(wide.add_policy("p", "p", to_vec!["bob", "data2", "read", "", "allow"]).await.unwrap());
+        assert!(wide.add_policy("p", "p", to_vec!["bob", "data2", "read", "", "deny"]).await.unwrap());
+        assert!(wide.remove_policy("p", "p", to_vec!["bob", "data2", "read", "", "deny"]).await.unwrap());
+        let mut m = crate::testing::model().await;
+        wide.load_policy(&mut m).await.unwrap();
+        let mut rules = m.get_policy("p", "p");
+        rules.sort();
+        assert_eq!(rules, vec![to_vec!["alice", "data1", "read"], to_vec!["bob", "data2", "read", "", "allow"]]);
+        assert_eq!(wide.audit_log(0, 10).await.unwrap().len(), 4);
+        assert!(migrations::add_value_columns(&rb, "casbin_rule", 5).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_for_tenant() {
+        use casbin::prelude::*;
+        let rb = sqlite_rb().await;
+        let adapter = RbatisAdapter::new(&rb).await.unwrap().with_revision_tracking(true).with_audit(true);
+        adapter.db_sync().await.unwrap();
+        let mut acme = adapter.for_tenant("acme");
+        let mut initech = adapter.for_tenant("initech");
+        assert_eq!(acme.tenant(), Some("acme"));
+
+        let rule = to_vec!["alice", "data1", "read"];
+        assert!(acme.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(initech.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(initech.add_policy("p", "p", to_vec!["bob", "data2", "write"]).await.unwrap());
+        assert!(acme.add_policy("p", "p", rule.clone()).await.is_err());
+
+        let m = DefaultModel::from_file("examples/rbac_model.conf").await.unwrap();
+        let mut e = Enforcer::new(m, acme.clone()).await.unwrap();
+        assert_eq!(e.get_policy(), vec![rule.clone()]);
+        let revision = acme.revision().await.unwrap();
+
+        // the writes of a tenant do not touch the rules of another one
+        assert!(initech.remove_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(initech.update_policy("p", "p", to_vec!["bob", "data2", "write"], rule.clone()).await.unwrap());
+        assert!(!acme.remove_policy("p", "p", to_vec!["bob", "data2", "write"]).await.unwrap());
+        assert!(acme.remove_filtered_policy("p", "p", 0, to_vec!["bob"]).await.is_ok());
+        initech.clear_policy().await.unwrap();
+        assert!(acme.load_changes_since(revision, e.get_mut_model()).await.is_ok());
+        assert_eq!(e.get_policy(), vec![rule.clone()]);
+        e.load_policy().await.unwrap();
+        assert_eq!(e.get_policy(), vec![rule.clone()]);
+
+        e.add_policy(to_vec!["carol", "data3", "read"]).await.unwrap();
+        e.save_policy().await.unwrap();
+        let rules = adapter::load_page(&rb, &adapter.all_tenants().table().unwrap(), None, 100).await.unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(acme.audit_log(0, 100).await.unwrap().len(), 5);
+        assert_eq!(initech.audit_log(0, 100).await.unwrap().len(), 6);
+
+        // the adapter itself is scoped to the empty tenant
+        let mut plain = adapter.clone();
+        assert!(plain.add_policy("p", "p", rule.clone()).await.unwrap());
+        let mut m = crate::testing::model().await;
+        plain.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), vec![rule.clone()]);
+        plain.save_policy(&mut m).await.unwrap();
+        assert!(plain.remove_filtered_policy("p", "p", 0, to_vec!["alice"]).await.unwrap());
+        plain.clear_policy().await.unwrap();
+        e.load_policy().await.unwrap();
+        assert_eq!(e.get_policy().len(), 2);
+        assert_eq!(plain.audit_log(0, 100).await.unwrap().len(), 4);
+
+        // the handle of all tenants only reads
+        let mut all = adapter.all_tenants();
+        let mut m = crate::testing::model().await;
+        all.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p").len(), 2);
+        assert_eq!(all.audit_log(0, 100).await.unwrap().len(), 15);
+        let err = all.add_policy("p", "p", rule.clone()).await.unwrap_err();
+        assert!(matches!(Error::downcast(&err), Some(Error::InvalidInput(_))));
+        assert!(all.clear_policy().await.is_err());
+        assert!(all.in_transaction(&rb).clear_policy().await.is_err());
+        assert_eq!(all.all_tenants().for_tenant("acme").write_table().unwrap().tenant.as_deref(), Some("acme"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_schema() {
+        let rb = sqlite_rb().await;
+        let adapter = RbatisAdapter::new(&rb).await.unwrap();
+        let err = adapter.verify_schema().await.unwrap_err();
+        assert!(matches!(Error::downcast(&err), Some(Error::SchemaMismatch(_))));
+        adapter.db_sync().await.unwrap();
+        adapter.verify_schema().await.unwrap();
+        adapter.for_tenant("acme").verify_schema().await.unwrap();
+        let err = adapter.clone().with_arity(7).verify_schema().await.unwrap_err();
+        assert!(err.to_string().contains("column v6 is missing"));
+
+        // a table of another tool
+        rb.exec(
+            "CREATE TABLE other_rule (id INTEGER PRIMARY KEY, ptype VARCHAR NOT NULL, v0 TEXT, v1 TEXT, v2 INTEGER)",
+            vec![],
+        )
+        .await
+        .unwrap();
+        let err = adapter.clone().with_table_name("other_rule").with_arity(3).verify_schema().await.unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("column v0 is nullable"));
+        assert!(msg.contains("column v2 is INTEGER, not a string"));
+        assert!(msg.contains("no unique key over exactly (ptype, v0, v1, v2)"));
+        assert!(!msg.contains("column tenant is missing"));
+        let tenant = adapter.with_table_name("other_rule").with_arity(3).for_tenant("acme");
+        let err = tenant.verify_schema().await.unwrap_err();
+        assert!(err.to_string().contains("column tenant is missing"));
+    }
+
+    #[tokio::test]
+    async fn test_table_without_tenant() {
+        let rb = sqlite_rb().await;
+        let ddl = "CREATE TABLE casbin_rule (id INTEGER PRIMARY KEY, ptype VARCHAR(12) NOT NULL, \
+            v0 VARCHAR(128) NOT NULL, v1 VARCHAR(128) NOT NULL, v2 VARCHAR(128) NOT NULL, UNIQUE(ptype, v0, v1, v2))";
+        rb.exec(ddl, vec![]).await.unwrap();
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_arity(3);
+        adapter.verify_schema().await.unwrap();
+        assert!(adapter.table().unwrap().tenant.is_none());
+
+        // the adapter is not scoped without the tenant column
+        adapter.add_policy("p", "p", to_vec!["alice", "data1", "read"]).await.unwrap();
+        let mut m = crate::testing::model().await;
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), vec![to_vec!["alice", "data1", "read"]]);
+        assert!(adapter.remove_policy("p", "p", to_vec!["alice", "data1", "read"]).await.unwrap());
+
+        // db_sync adds the column, and the adapter is scoped to the empty tenant from then on
+        adapter.db_sync().await.unwrap();
+        assert_eq!(adapter.table().unwrap().tenant.as_deref(), Some(""));
+        adapter.for_tenant("acme").add_policy("p", "p", to_vec!["bob", "data2", "read"]).await.unwrap();
+        adapter.clear_policy().await.unwrap();
+        let mut m = crate::testing::model().await;
+        adapter.all_tenants().load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), vec![to_vec!["bob", "data2", "read"]]);
+    }
+
+    #[tokio::test]
+    async fn test_value_too_long() {
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap();
+        adapter.db_sync().await.unwrap();
+        adapter.add_policy("p", "p", to_vec!["alice", "data1", "read"]).await.unwrap();
+
+        let long = "x".repeat(129);
+        let err = adapter
+            .add_policies("p", "p", vec![to_vec!["bob", "data1", "read"], to_vec!["bob", long, "read"]])
+            .await
+            .unwrap_err();
+        match Error::downcast(&err) {
+            Some(Error::ValueTooLong { ptype, rule, field, len, max }) => {
+                assert_eq!((ptype.as_str(), &rule[0], field.as_str(), *len, *max), ("p", &"bob".to_owned(), "v1", 129, 128));
+            }
+            _ => panic!("{err}"),
+        }
+        let mut m = crate::testing::model().await;
+        m.add_policy("p", "p", to_vec!["carol", "data1", "read"]);
+        m.add_policy("p", "p", to_vec!["carol", "data1", "read", "x".repeat(200)]);
+        assert!(adapter.save_policy(&mut m).await.is_err());
+        let err = adapter.add_policy("p", "p_with_a_long_ptype", to_vec!["alice"]).await.unwrap_err();
+        assert!(matches!(Error::downcast(&err), Some(Error::ValueTooLong { field, .. }) if field == "ptype"));
+
+        // nothing was written
+        let mut m = crate::testing::model().await;
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), vec![to_vec!["alice", "data1", "read"]]);
+
+        let mut adapter = adapter.with_max_lengths(32, 255);
+        assert!(adapter.add_policy("p", "p", to_vec!["bob", "x".repeat(200)]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_with_model() {
+        let m = crate::testing::model().await;
+        assert_eq!(RbatisAdapter::model_arity(&m), 4);
+        let indexes = |rb: RBatis| async move {
+            let sql = "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'casbin_rule' AND name LIKE 'idx_%' ORDER BY name";
+            #[derive(serde::Deserialize)]
+            struct Index {
+                name: String,
+            }
+            let indexes: Vec<Index> = rb.query_decode(sql, vec![]).await.unwrap();
+            indexes.into_iter().map(|x| x.name).collect::<Vec<_>>()
+        };
+
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_model(&m, true);
+        assert_eq!(adapter.arity(), 4);
+        adapter.db_sync().await.unwrap();
+        adapter.db_sync().await.unwrap();
+        assert_eq!(indexes(rb.clone()).await, vec!["idx_casbin_rule_v1", "idx_casbin_rule_v2"]);
+        adapter.verify_schema().await.unwrap();
+        assert!(adapter.add_policy("p", "p", to_vec!["alice", "domain1", "data1", "read", "x"]).await.is_err());
+
+        // the generated table is migrated by db_sync
+        let rb = sqlite_rb().await;
+        let adapter = RbatisAdapter::new(&rb).await.unwrap().with_arity(4);
+        for sql in adapter.model_ddl(&m, true).unwrap() {
+            rb.exec(&sql, vec![]).await.unwrap();
+        }
+        assert_eq!(indexes(rb.clone()).await, vec!["idx_casbin_rule_v1", "idx_casbin_rule_v2"]);
+        adapter.db_sync().await.unwrap();
+        adapter.verify_schema().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_field_indexes() {
+        let rb = sqlite_rb().await;
+        let adapter = RbatisAdapter::new(&rb).await.unwrap().with_field_indexes([6]);
+        let err = adapter.db_sync().await.unwrap_err();
+        assert!(matches!(Error::downcast(&err), Some(Error::SchemaMismatch(_))));
+
+        let adapter = RbatisAdapter::new(&rb).await.unwrap().with_field_indexes([1, 0]);
+        assert_eq!(adapter.field_indexes(), vec![0, 1]);
+        adapter.db_sync().await.unwrap();
+        // the filter of `remove_filtered_policy("p", "p", 1, ["domain1"])` and of a section
+        for condition in ["ptype = ? AND v1 = ?", "ptype LIKE ? ESCAPE '!' AND v1 = ?"] {
+            let sql = format!("EXPLAIN QUERY PLAN SELECT * FROM casbin_rule WHERE {condition}");
+            let plan: rbs::Value = rb.query(&sql, vec!["p".into(), "domain1".into()]).await.unwrap();
+            assert!(plan.to_string().contains("USING INDEX idx_casbin_rule_v1"), "{plan}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strict() {
+        let rb = sqlite_rb().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_arity(3);
+        adapter.db_sync().await.unwrap();
+        let wide = to_vec!["alice", "data1", "read", "allow"];
+        // skipped by default
+        assert!(!adapter.add_policy("p", "p", vec![]).await.unwrap());
+        assert!(!adapter.remove_policy("p", "p", wide.clone()).await.unwrap());
+        assert!(!adapter.remove_filtered_policy("p", "p", 3, to_vec!["read"]).await.unwrap());
+
+        let mut adapter = adapter.with_strict(true);
+        let invalid = |err: casbin::Error| matches!(Error::downcast(&err), Some(Error::InvalidInput(_)));
+        assert!(invalid(adapter.add_policy("p", "", to_vec!["alice"]).await.unwrap_err()));
+        assert!(invalid(adapter.add_policies("p", "p", vec![to_vec!["alice"], vec![]]).await.unwrap_err()));
+        assert!(invalid(adapter.remove_filtered_policy("p", "p", 3, to_vec!["read"]).await.unwrap_err()));
+        let err = adapter.remove_policy("p", "p", wide.clone()).await.unwrap_err();
+        assert!(matches!(Error::downcast(&err), Some(Error::SchemaMismatch(_))));
+        assert!(adapter.update_policy("p", "p", wide, to_vec!["alice"]).await.is_err());
+
+        let mut m = crate::testing::model().await;
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_csv() {
+        let rb = sqlite_rb().await;
+        let adapter = RbatisAdapter::new(&rb).await.unwrap();
+        adapter.db_sync().await.unwrap();
+        let example = include_str!("../examples/rbac_policy.csv");
+        assert_eq!(adapter.import_csv(example.as_bytes(), ImportStrategy::Replace).await.unwrap(), 5);
+        let mut out = vec![];
+        assert_eq!(adapter.export_csv(&mut out).await.unwrap(), 5);
+        assert_eq!(String::from_utf8(out).unwrap().trim_end(), example.trim_end());
+
+        // the quoted values are stored as they are
+        let csv = "p, \"carol, jr\", \"say \"\"hi\"\"\", \" read\"\np, alice, data1, read\n";
+        assert_eq!(adapter.import_csv(csv.as_bytes(), ImportStrategy::Merge).await.unwrap(), 1);
+        let mut m = crate::testing::model().await;
+        let mut reader = adapter.clone();
+        reader.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").contains(&to_vec!["carol, jr", "say \"hi\"", " read"]));
+        let mut out = vec![];
+        assert_eq!(adapter.export_csv(&mut out).await.unwrap(), 6);
+        assert!(String::from_utf8(out).unwrap().trim_end().ends_with(csv.lines().next().unwrap()));
+
+        // a stored rule fails the whole import
+        let csv = "p, dave, data3, read\np, bob, data2, write\n";
+        assert!(adapter.import_csv(csv.as_bytes(), ImportStrategy::Fail).await.is_err());
+        assert_eq!(adapter.import_csv(csv.as_bytes(), ImportStrategy::Merge).await.unwrap(), 1);
+        let err = adapter.import_csv("p, eve\np, \"x\" y\n".as_bytes(), ImportStrategy::Merge).await.unwrap_err();
+        assert_eq!(Error::downcast(&err).unwrap().to_string(), "line 2: a quoted value is followed by more text");
+
+        assert_eq!(adapter.import_csv(csv.as_bytes(), ImportStrategy::Replace).await.unwrap(), 2);
+        let mut m = crate::testing::model().await;
+        reader.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p").len(), 2);
+        assert!(m.get_policy("g", "g").is_empty());
+    }
+
     #[tokio::test]
     async fn test_adapter() {
         use casbin::prelude::*;
@@ -0,0 +1,262 @@
+//! The builder of a validated [`RbatisAdapter`].
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use casbin::Result;
+use rbatis::RBatis;
+
+use crate::dialect::Dialect;
+use crate::error::Error;
+use crate::models::{ColumnLengths, ConflictStrategy, SaveMode, ARITY, BATCH_SIZE, PAGE_SIZE, TABLE_NAME};
+use crate::RbatisAdapter;
+
+/// Configures a [`RbatisAdapter`], the options are checked by [`RbatisAdapterBuilder::build`].
+/// ```rust,ignore
+/// let adapter = RbatisAdapter::builder(&primary)
+///     .table_name("svc_a_casbin_rule")
+///     .arity(8)
+///     .conflict_strategy(ConflictStrategy::Skip)
+///     .replica(&replica)
+///     .auto_sync(true)
+///     .build()
+///     .await?;
+/// ```
+#[derive(Clone)]
+pub struct RbatisAdapterBuilder {
+    pool: RBatis,
+    replica: Option<RBatis>,
+    primary_reads_after_write: Option<Duration>,
+    table_name: String,
+    arity: usize,
+    batch_size: usize,
+    page_size: usize,
+    save_mode: SaveMode,
+    conflict: ConflictStrategy,
+    strict: bool,
+    lengths: ColumnLengths,
+    field_indexes: BTreeSet<usize>,
+    track_revision: bool,
+    audit: bool,
+    auto_sync: bool,
+    verify_schema: bool,
+}
+
+impl RbatisAdapterBuilder {
+    /// A builder of an adapter whose reads and writes use `rb`, with the defaults of [`RbatisAdapter::new`].
+    pub fn new(rb: &RBatis) -> Self {
+        Self {
+            pool: rb.clone(),
+            replica: None,
+            primary_reads_after_write: None,
+            table_name: TABLE_NAME.to_owned(),
+            arity: ARITY,
+            batch_size: BATCH_SIZE,
+            page_size: PAGE_SIZE,
+            save_mode: SaveMode::default(),
+            conflict: ConflictStrategy::default(),
+            strict: false,
+            lengths: ColumnLengths::default(),
+            field_indexes: BTreeSet::new(),
+            track_revision: false,
+            audit: false,
+            auto_sync: false,
+            verify_schema: false,
+        }
+    }
+
+    /// See [`RbatisAdapter::with_table_name`].
+    pub fn table_name(mut self, table_name: &str) -> Self {
+        self.table_name = table_name.to_owned();
+        self
+    }
+
+    /// See [`RbatisAdapter::with_arity`], 0 is an error.
+    pub fn arity(mut self, arity: usize) -> Self {
+        self.arity = arity;
+        self
+    }
+
+    /// See [`RbatisAdapter::with_max_lengths`], 0 is an error.
+    pub fn max_lengths(mut self, ptype_len: usize, value_len: usize) -> Self {
+        self.lengths = ColumnLengths {
+            ptype: ptype_len,
+            value: value_len,
+        };
+        self
+    }
+
+    /// See [`RbatisAdapter::with_field_indexes`], a field outside of the value columns is an error.
+    pub fn field_indexes(mut self, fields: impl IntoIterator<Item = usize>) -> Self {
+        self.field_indexes.extend(fields);
+        self
+    }
+
+    /// See [`RbatisAdapter::with_batch_size`], 0 is an error.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// See [`RbatisAdapter::with_page_size`], 0 is an error.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// See [`RbatisAdapter::with_save_mode`].
+    pub fn save_mode(mut self, save_mode: SaveMode) -> Self {
+        self.save_mode = save_mode;
+        self
+    }
+
+    /// See [`RbatisAdapter::with_conflict_strategy`].
+    pub fn conflict_strategy(mut self, conflict: ConflictStrategy) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    /// See [`RbatisAdapter::with_strict`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// See [`RbatisAdapter::with_revision_tracking`].
+    pub fn revision_tracking(mut self, track_revision: bool) -> Self {
+        self.track_revision = track_revision;
+        self
+    }
+
+    /// See [`RbatisAdapter::with_audit`].
+    pub fn audit(mut self, audit: bool) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// The pool of the reads, see [`RbatisAdapter::with_replica`]. Its driver must be the one of the primary pool.
+    pub fn replica(mut self, replica: &RBatis) -> Self {
+        self.replica = Some(replica.clone());
+        self
+    }
+
+    /// See [`RbatisAdapter::with_primary_reads_after_write`], it needs a replica.
+    pub fn primary_reads_after_write(mut self, window: Duration) -> Self {
+        self.primary_reads_after_write = Some(window);
+        self
+    }
+
+    /// Call [`RbatisAdapter::db_sync`] in `build`, so the tables exist and are migrated.
+    pub fn auto_sync(mut self, auto_sync: bool) -> Self {
+        self.auto_sync = auto_sync;
+        self
+    }
+
+    /// Call [`RbatisAdapter::verify_schema`] in `build`, after `db_sync` when `auto_sync` is set.
+    pub fn verify_schema(mut self, verify_schema: bool) -> Self {
+        self.verify_schema = verify_schema;
+        self
+    }
+
+    /// The adapter of the options, an invalid option is an [`Error::InvalidInput`].
+    pub async fn build(self) -> Result<RbatisAdapter> {
+        let dialect = Dialect::of(&self.pool)?;
+        dialect.quote_table(&self.table_name)?;
+        for (name, value) in [
+            ("arity", self.arity),
+            ("batch size", self.batch_size),
+            ("page size", self.page_size),
+            ("ptype length", self.lengths.ptype),
+            ("value length", self.lengths.value),
+        ] {
+            if value == 0 {
+                let err_msg = format!("the {name} of the adapter must be at least 1");
+                return Err(Error::InvalidInput(err_msg).into());
+            }
+        }
+        if let Some(max) = dialect.max_arity().filter(|max| self.arity > *max) {
+            let err_msg = format!("the arity of the adapter is at most {max}, the unique key can not cover more columns");
+            return Err(Error::InvalidInput(err_msg).into());
+        }
+        if let Some(field) = self.field_indexes.iter().find(|x| **x >= self.arity) {
+            let err_msg = format!("index on v{field}, the table has {} columns", self.arity);
+            return Err(Error::InvalidInput(err_msg).into());
+        }
+        if let Some(replica) = &self.replica {
+            if Dialect::of(replica)? != dialect {
+                let err_msg = "the replica pool has another driver than the primary pool".to_owned();
+                return Err(Error::InvalidInput(err_msg).into());
+            }
+        }
+        if self.replica.is_none() && self.primary_reads_after_write.is_some() {
+            let err_msg = "the primary reads after a write need a replica pool".to_owned();
+            return Err(Error::InvalidInput(err_msg).into());
+        }
+
+        let mut adapter = RbatisAdapter::new(&self.pool)
+            .await?
+            .with_table_name(&self.table_name)
+            .with_arity(self.arity)
+            .with_max_lengths(self.lengths.ptype, self.lengths.value)
+            .with_field_indexes(self.field_indexes)
+            .with_batch_size(self.batch_size)
+            .with_page_size(self.page_size)
+            .with_save_mode(self.save_mode)
+            .with_conflict_strategy(self.conflict)
+            .with_strict(self.strict)
+            .with_revision_tracking(self.track_revision)
+            .with_audit(self.audit);
+        if let Some(replica) = &self.replica {
+            adapter = adapter.with_replica(replica);
+        }
+        if let Some(window) = self.primary_reads_after_write {
+            adapter = adapter.with_primary_reads_after_write(window);
+        }
+        if self.auto_sync {
+            adapter.db_sync().await?;
+        }
+        if self.verify_schema {
+            adapter.verify_schema().await?;
+        }
+        Ok(adapter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::sqlite_rbatis;
+    use crate::to_vec;
+    use casbin::Adapter;
+
+    #[tokio::test]
+    async fn test_build() {
+        let rb = sqlite_rbatis().await;
+        let invalid = |builder: RbatisAdapterBuilder| async move {
+            let err = builder.build().await.err().unwrap();
+            matches!(Error::downcast(&err), Some(Error::InvalidInput(_)))
+        };
+        assert!(invalid(RbatisAdapter::builder(&rb).arity(0)).await);
+        assert!(invalid(RbatisAdapter::builder(&rb).batch_size(0)).await);
+        assert!(invalid(RbatisAdapter::builder(&rb).table_name("auth.")).await);
+        assert!(invalid(RbatisAdapter::builder(&rb).field_indexes([1, 6])).await);
+        assert!(invalid(RbatisAdapter::builder(&rb).primary_reads_after_write(Duration::from_secs(1))).await);
+
+        let mut adapter = RbatisAdapter::builder(&rb)
+            .table_name("svc_a_casbin_rule")
+            .arity(8)
+            .field_indexes([1, 7])
+            .conflict_strategy(ConflictStrategy::Report)
+            .strict(true)
+            .auto_sync(true)
+            .verify_schema(true)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!((adapter.table_name(), adapter.arity()), ("svc_a_casbin_rule", 8));
+        assert_eq!(adapter.field_indexes(), vec![1, 7]);
+        let rule = to_vec!["alice", "data1", "read", "", "", "", "", "allow"];
+        assert!(adapter.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(!adapter.add_policy("p", "p", rule).await.unwrap());
+        assert!(adapter.add_policy("p", "p", vec![]).await.is_err());
+    }
+}
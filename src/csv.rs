@@ -0,0 +1,153 @@
+//! casbin's csv policy format, a rule per line like `p, alice, data1, read`.
+//!
+//! A value with a comma, a quote, a line break or surrounding whitespace is quoted, and its quotes are doubled.
+use std::borrow::Cow;
+use std::io::{self, BufRead, Write};
+
+use casbin::Result;
+
+use crate::error::Error;
+
+/// Writes the rule as a line of the policy file.
+pub(crate) fn write_record(w: &mut impl Write, ptype: &str, rule: &[String]) -> io::Result<()> {
+    let fields = std::iter::once(ptype)
+        .chain(rule.iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<_>>();
+    writeln!(w, "{}", fields.join(", "))
+}
+
+// quotes a value which would not be read back as it is, a leading `#` would start a comment.
+fn quote(value: &str) -> Cow<'_, str> {
+    let plain = !value.contains([',', '"', '\n', '\r']) && value.trim() == value && !value.starts_with('#');
+    match plain {
+        true => Cow::Borrowed(value),
+        false => Cow::Owned(format!("\"{}\"", value.replace('"', "\"\""))),
+    }
+}
+
+/// Reads the records of the policy file as `(ptype, rule)` pairs, the empty lines and the `#` comments are skipped.
+pub(crate) fn read_records(reader: impl BufRead) -> Result<Vec<(String, Vec<String>)>> {
+    let mut records = vec![];
+    let mut lines = reader.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let mut record = line?;
+        if record.trim().is_empty() || record.trim_start().starts_with('#') {
+            continue;
+        }
+        // a quoted value continues on the next line until its quotes are closed
+        while ends_quoted(&record) {
+            let Some((_, line)) = lines.next() else {
+                break;
+            };
+            record.push('\n');
+            record.push_str(&line?);
+        }
+        let mut fields = parse_record(&record).map_err(|msg| Error::InvalidInput(format!("line {}: {msg}", i + 1)))?;
+        let ptype = fields.remove(0);
+        records.push((ptype, fields));
+    }
+    Ok(records)
+}
+
+// whether the record ends inside a quoted value, the quotes inside an unquoted value are plain text.
+fn ends_quoted(record: &str) -> bool {
+    let mut quoted = false;
+    let mut field_start = true;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.next_if_eq(&'"').is_some() => {}
+            '"' if quoted => quoted = false,
+            '"' if field_start => quoted = true,
+            ',' if !quoted => {
+                field_start = true;
+                continue;
+            }
+            ' ' | '\t' => continue,
+            _ => {}
+        }
+        field_start = false;
+    }
+    quoted
+}
+
+// the values of a record, the unquoted ones are trimmed.
+fn parse_record(record: &str) -> std::result::Result<Vec<String>, &'static str> {
+    let mut fields = vec![];
+    let mut chars = record.chars().peekable();
+    loop {
+        while chars.next_if(|x| *x == ' ' || *x == '\t').is_some() {}
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    None => return Err("the quoted value is not closed"),
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                }
+            }
+            while chars.next_if(|x| *x == ' ' || *x == '\t').is_some() {}
+            if chars.peek().is_some_and(|x| *x != ',') {
+                return Err("a quoted value is followed by more text");
+            }
+        } else {
+            while let Some(c) = chars.next_if(|x| *x != ',') {
+                field.push(c);
+            }
+            field = field.trim().to_owned();
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_vec;
+
+    #[test]
+    fn test_csv() {
+        let rules = vec![
+            ("p".to_owned(), to_vec!["alice", "data1", "read"]),
+            ("p".to_owned(), to_vec!["bob", "", "a, b", "say \"hi\""]),
+            ("g".to_owned(), to_vec![" carol", "#admin", "line\nbreak"]),
+        ];
+        let mut out = vec![];
+        for (ptype, rule) in &rules {
+            write_record(&mut out, ptype, rule).unwrap();
+        }
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text,
+            "p, alice, data1, read\np, bob, , \"a, b\", \"say \"\"hi\"\"\"\ng, \" carol\", \"#admin\", \"line\nbreak\"\n"
+        );
+        assert_eq!(read_records(text.as_bytes()).unwrap(), rules);
+
+        let text = "# a comment\n\np,alice , data1,read\r\n  g, \"bob\" , admin\n";
+        assert_eq!(
+            read_records(text.as_bytes()).unwrap(),
+            vec![
+                ("p".to_owned(), to_vec!["alice", "data1", "read"]),
+                ("g".to_owned(), to_vec!["bob", "admin"])
+            ]
+        );
+        let err = read_records("p, alice\np, \"bob\" x, read\n".as_bytes()).unwrap_err();
+        assert!(Error::downcast(&err).unwrap().to_string().starts_with("line 2: "));
+        assert!(read_records("p, \"alice".as_bytes()).is_err());
+
+        // a quote inside an unquoted value does not continue the record
+        let text = "p, alice, say\"hi, read\np, bob, data2, \"a\"\"\nb\"\n";
+        assert_eq!(
+            read_records(text.as_bytes()).unwrap(),
+            vec![
+                ("p".to_owned(), to_vec!["alice", "say\"hi", "read"]),
+                ("p".to_owned(), to_vec!["bob", "data2", "a\"\nb"])
+            ]
+        );
+    }
+}
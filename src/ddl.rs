@@ -0,0 +1,93 @@
+//! The DDL of the rule table for a casbin model: the value columns its definitions need,
+//! and the indexes of the fields it uses as domain.
+use std::collections::BTreeSet;
+
+use casbin::{Model, Result};
+
+use crate::dialect::Dialect;
+
+/// The number of value columns the `p*` and `g*` definitions of the model need, at least 1.
+pub(crate) fn model_arity(m: &dyn Model) -> usize {
+    let model = m.get_model();
+    let p = model.get("p").into_iter().flat_map(|x| x.values()).map(|x| x.tokens.len());
+    // the role definitions are like `_, _, _`
+    let g = model.get("g").into_iter().flat_map(|x| x.values()).map(|x| x.value.matches('_').count());
+    p.chain(g).max().unwrap_or_default().max(1)
+}
+
+/// The indexes of the value columns the model uses as domain: the `dom` or `domain` field of a policy
+/// definition, and the third field of a role definition with domains.
+pub(crate) fn domain_fields(m: &dyn Model) -> BTreeSet<usize> {
+    let model = m.get_model();
+    let mut fields = BTreeSet::new();
+    for (key, ast) in model.get("p").into_iter().flatten() {
+        let prefix = format!("{key}_");
+        let names = ast.tokens.iter().map(|x| x.strip_prefix(&prefix).unwrap_or(x));
+        fields.extend(names.enumerate().filter(|(_, x)| matches!(*x, "dom" | "domain")).map(|(i, _)| i));
+    }
+    if model.get("g").into_iter().flat_map(|x| x.values()).any(|x| x.value.matches('_').count() == 3) {
+        fields.insert(2);
+    }
+    fields
+}
+
+/// The `CREATE INDEX` statement of the index on value column `field`, named `idx_{table}_v{field}`.
+/// The index leads with the value, so the rules of a value are found for any ptype or section.
+pub(crate) fn field_index_sql(dialect: Dialect, table_name: &str, field: usize) -> Result<String> {
+    let column = format!("v{field}");
+    dialect.create_index_sql(table_name, &column, &[&column, "ptype"])
+}
+
+/// The statements which create the rule table of `arity` value columns and the indexes on `fields`,
+/// like the scripts in `sql`.
+pub(crate) fn rule_table_ddl(dialect: Dialect, table_name: &str, arity: usize, fields: &BTreeSet<usize>) -> Result<Vec<String>> {
+    let columns = dialect.rule_table_columns(table_name, arity, false)?;
+    let mut statements = vec![dialect.create_table_sql(table_name, &columns)?];
+    for field in fields {
+        statements.push(format!("{};", field_index_sql(dialect, table_name, *field)?));
+    }
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use casbin::DefaultModel;
+
+    #[tokio::test]
+    async fn test_model_ddl() {
+        let m = crate::testing::model().await;
+        assert_eq!(model_arity(&m), 4);
+        assert_eq!(domain_fields(&m), BTreeSet::from([1, 2]));
+
+        let m = DefaultModel::from_str(
+            "
+[request_definition]
+r = sub, obj, act
+
+[policy_definition]
+p = sub, obj, act
+p2 = sub, obj, act, eft, tenant, region
+
+[role_definition]
+g = _, _
+
+[policy_effect]
+e = some(where (p.eft == allow))
+
+[matchers]
+m = g(r.sub, p.sub) && r.obj == p.obj && r.act == p.act
+",
+        )
+        .await
+        .unwrap();
+        assert_eq!(model_arity(&m), 6);
+        assert!(domain_fields(&m).is_empty());
+
+        let statements = rule_table_ddl(Dialect::Postgres, "casbin_rule", 4, &BTreeSet::from([1])).unwrap();
+        assert_eq!(statements.len(), 2);
+        assert!(statements[0].contains("  v3 VARCHAR NOT NULL,\n"));
+        assert!(!statements[0].contains("v4"));
+        assert_eq!(statements[1], "CREATE INDEX \"idx_casbin_rule_v1\" ON \"casbin_rule\" (v1, ptype);");
+    }
+}
@@ -0,0 +1,493 @@
+use casbin::Result;
+use rbs::Value;
+
+use crate::error::{query_error, Error};
+use crate::models::{PTYPE_LEN, TENANT_LEN, VALUE_LEN};
+
+/// The sql dialect of the database behind a rbatis pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Dialect {
+    Mysql,
+    Postgres,
+    Sqlite,
+    Mssql,
+    /// Any other driver, identifiers are quoted the ansi way and no ddl is available.
+    Other,
+}
+
+impl Dialect {
+    /// Resolves the dialect from the rbatis driver type.
+    pub fn from_driver(driver_type: &str) -> Self {
+        match driver_type {
+            "mysql" => Self::Mysql,
+            "postgres" => Self::Postgres,
+            "sqlite" => Self::Sqlite,
+            "mssql" => Self::Mssql,
+            _ => Self::Other,
+        }
+    }
+
+    /// Resolves the dialect of an initialized rbatis pool.
+    pub fn of(rb: &rbatis::RBatis) -> Result<Self> {
+        rb.driver_type()
+            .map(Self::from_driver)
+            .map_err(query_error)
+    }
+
+    /// Quotes a single identifier, escaping the quote character inside it.
+    pub fn quote_ident(&self, ident: &str) -> String {
+        match self {
+            Self::Mysql => format!("`{}`", ident.replace('`', "``")),
+            Self::Postgres | Self::Sqlite | Self::Other => format!("\"{}\"", ident.replace('"', "\"\"")),
+            Self::Mssql => format!("[{}]", ident.replace(']', "]]")),
+        }
+    }
+
+    /// Quotes a table name, which may be qualified with a schema as `schema.table`.
+    pub fn quote_table(&self, table_name: &str) -> Result<String> {
+        let parts = table_name.split('.').collect::<Vec<_>>();
+        if parts.iter().any(|x| x.trim().is_empty()) {
+            let err_msg = format!("invalid table name: `{}`", table_name);
+            return Err(Error::InvalidInput(err_msg).into());
+        }
+        Ok(parts.iter().map(|x| self.quote_ident(x)).collect::<Vec<_>>().join("."))
+    }
+
+    /// The maximum number of bind parameters of one statement.
+    pub fn max_bind_params(&self) -> usize {
+        match self {
+            Self::Mysql => 65535,
+            // the parameter count is sent as an int16
+            Self::Postgres => 32767,
+            // SQLITE_MAX_VARIABLE_NUMBER since sqlite 3.32
+            Self::Sqlite => 32766,
+            Self::Mssql => 2100,
+            Self::Other => 999,
+        }
+    }
+
+    /// The column definition of an auto increment primary key.
+    pub fn id_column(&self) -> &'static str {
+        match self {
+            Self::Mysql => "id INT NOT NULL AUTO_INCREMENT PRIMARY KEY",
+            Self::Postgres => "id SERIAL PRIMARY KEY",
+            Self::Sqlite => "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            Self::Mssql | Self::Other => "id INT IDENTITY(1, 1) PRIMARY KEY",
+        }
+    }
+
+    /// The string type of a column, `len` is only enforced by mysql and mssql.
+    pub fn varchar(&self, len: usize) -> String {
+        match self {
+            Self::Mysql => format!("VARCHAR({len})"),
+            Self::Postgres | Self::Sqlite => "VARCHAR".to_owned(),
+            Self::Mssql | Self::Other => format!("NVARCHAR({len})"),
+        }
+    }
+
+    /// The type of a date and time column.
+    pub fn datetime(&self) -> &'static str {
+        match self {
+            Self::Mysql => "DATETIME",
+            Self::Postgres | Self::Sqlite | Self::Other => "TIMESTAMP",
+            // TIMESTAMP is a row version there
+            Self::Mssql => "DATETIME2",
+        }
+    }
+
+    /// Builds a `CREATE TABLE` statement which does nothing when the table already exists.
+    pub fn create_table_sql(&self, table_name: &str, columns: &[String]) -> Result<String> {
+        let table = self.quote_table(table_name)?;
+        let columns = columns.join(",\n  ");
+        let sql = match self {
+            Self::Mysql => format!(
+                "CREATE TABLE IF NOT EXISTS {table} (\n  {columns}\n) ENGINE = InnoDB DEFAULT CHARSET = utf8;"
+            ),
+            Self::Postgres | Self::Sqlite => format!("CREATE TABLE IF NOT EXISTS {table} (\n  {columns}\n);"),
+            Self::Mssql => format!(
+                "IF OBJECT_ID(N'{object}', N'U') IS NULL\nCREATE TABLE {table} (\n  {columns}\n);",
+                object = table.replace('\'', "''")
+            ),
+            Self::Other => {
+                let err_msg = format!("unsupported driver type, please create table {} manually. ", table);
+                return Err(Error::UnsupportedDriver(err_msg).into());
+            }
+        };
+        Ok(sql)
+    }
+
+    /// The column definitions of a rule table with `arity` value columns, and a `tenant` column when `tenant` is set.
+    pub fn rule_table_columns(&self, table_name: &str, arity: usize, tenant: bool) -> Result<Vec<String>> {
+        let mut columns = vec![self.id_column().to_owned()];
+        if tenant {
+            columns.push(self.tenant_column());
+        }
+        columns.push(format!("ptype {} NOT NULL", self.varchar(PTYPE_LEN)));
+        columns.extend((0..arity).map(|i| format!("v{i} {} NOT NULL", self.varchar(VALUE_LEN))));
+        columns.push(self.unique_constraint(table_name, arity, tenant)?);
+        Ok(columns)
+    }
+
+    /// The definition of the `tenant` column, the rules without a tenant have an empty one.
+    pub fn tenant_column(&self) -> String {
+        format!("tenant {} NOT NULL DEFAULT ''", self.varchar(TENANT_LEN))
+    }
+
+    /// The unique constraint of a rule table over its `tenant` (when set), `ptype` and value columns.
+    /// Fails when the key is longer than the database can index, a key over a prefix of the values
+    /// would reject the distinct rules which only differ after it.
+    pub fn unique_constraint(&self, table_name: &str, arity: usize, tenant: bool) -> Result<String> {
+        let fixed = if tenant { PTYPE_LEN + TENANT_LEN } else { PTYPE_LEN };
+        if let Some(max) = self.max_key_chars().filter(|max| fixed + VALUE_LEN * arity > *max) {
+            let err_msg = format!(
+                "the unique key over {arity} value columns holds {} characters, the database indexes up to {max}",
+                fixed + VALUE_LEN * arity
+            );
+            return Err(Error::SchemaMismatch(err_msg).into());
+        }
+        // constraint names must be unique per schema on some databases
+        let constraint = self.quote_ident(&format!("unique_key_{}", unqualified(table_name)));
+        let columns = tenant
+            .then(|| "tenant".to_owned())
+            .into_iter()
+            .chain(std::iter::once("ptype".to_owned()))
+            .chain((0..arity).map(|i| format!("v{i}")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(format!("CONSTRAINT {constraint} UNIQUE({columns})"))
+    }
+
+    /// The most value columns a unique key with the `tenant` column covers, `None` without a limit.
+    pub fn max_arity(&self) -> Option<usize> {
+        self.max_key_chars().map(|max| (max - PTYPE_LEN - TENANT_LEN) / VALUE_LEN)
+    }
+
+    // the characters of the longest unique key, innodb keys hold 3072 bytes (utf8 is 3 bytes per char)
+    // and mssql index keys 1700 bytes (nvarchar is 2 bytes per char).
+    fn max_key_chars(&self) -> Option<usize> {
+        match self {
+            Self::Mysql => Some(1024),
+            Self::Mssql => Some(850),
+            _ => None,
+        }
+    }
+
+    /// Builds an `ALTER TABLE` statement which adds the column `definition`.
+    pub fn add_column_sql(&self, table_name: &str, definition: &str) -> Result<String> {
+        let table = self.quote_table(table_name)?;
+        Ok(match self {
+            Self::Mssql => format!("ALTER TABLE {table} ADD {definition}"),
+            _ => format!("ALTER TABLE {table} ADD COLUMN {definition}"),
+        })
+    }
+
+    /// Builds an `ALTER TABLE` statement which adds the table constraint `definition`.
+    pub fn add_constraint_sql(&self, table_name: &str, definition: &str) -> Result<String> {
+        Ok(format!("ALTER TABLE {} ADD {definition}", self.quote_table(table_name)?))
+    }
+
+    /// Builds an `ALTER TABLE` statement which drops the unique constraint `name`.
+    pub fn drop_unique_sql(&self, table_name: &str, name: &str) -> Result<String> {
+        let table = self.quote_table(table_name)?;
+        let name = self.quote_ident(name);
+        Ok(match self {
+            Self::Mysql => format!("ALTER TABLE {table} DROP INDEX {name}"),
+            _ => format!("ALTER TABLE {table} DROP CONSTRAINT {name}"),
+        })
+    }
+
+    /// Builds a statement which renames a table, `new_name` is unqualified and stays in the same schema.
+    pub fn rename_table_sql(&self, table_name: &str, new_name: &str) -> Result<String> {
+        let table = self.quote_table(table_name)?;
+        Ok(match self {
+            Self::Mssql => format!("EXEC sp_rename '{}', '{}'", table.replace('\'', "''"), new_name.replace('\'', "''")),
+            _ => format!("ALTER TABLE {table} RENAME TO {}", self.quote_ident(new_name)),
+        })
+    }
+
+    /// Builds a query of the names of the unique constraints of a table, in column `name`.
+    /// The unique constraints of sqlite can not be dropped, so there is none.
+    pub fn unique_keys_sql(&self, table_name: &str) -> Result<(String, Vec<Value>)> {
+        let table = self.quote_table(table_name)?;
+        let schema = table_name.rsplit_once('.').map(|(schema, _)| schema);
+        let query = match self {
+            Self::Mysql => (
+                "SELECT constraint_name AS name FROM information_schema.table_constraints \
+                 WHERE table_schema = COALESCE(?, DATABASE()) AND table_name = ? AND constraint_type = 'UNIQUE'"
+                    .to_owned(),
+                vec![schema.map(Value::from).unwrap_or_default(), unqualified(table_name).into()],
+            ),
+            Self::Postgres => (
+                "SELECT conname AS name FROM pg_constraint WHERE conrelid = to_regclass(?) AND contype = 'u'".to_owned(),
+                vec![table.into()],
+            ),
+            Self::Sqlite => ("SELECT '' AS name WHERE 0 = 1".to_owned(), vec![]),
+            Self::Mssql => (
+                "SELECT name FROM sys.key_constraints WHERE parent_object_id = OBJECT_ID(?) AND type = 'UQ'".to_owned(),
+                vec![table.into()],
+            ),
+            Self::Other => {
+                let err_msg = format!("unsupported driver type, can not inspect table {}. ", table);
+                return Err(Error::UnsupportedDriver(err_msg).into());
+            }
+        };
+        Ok(query)
+    }
+
+    /// Builds a query of the columns of a table, with the columns `name`, `data_type`, `max_length`
+    /// (the characters of a string column, null when it is not limited) and `nullable` (1 or 0).
+    pub fn columns_sql(&self, table_name: &str) -> Result<(String, Vec<Value>)> {
+        let table = self.quote_table(table_name)?;
+        let schema = table_name.rsplit_once('.').map(|(schema, _)| schema);
+        let name = unqualified(table_name);
+        let query = match self {
+            Self::Mysql => (
+                "SELECT column_name AS name, data_type, character_maximum_length AS max_length, \
+                 CASE is_nullable WHEN 'YES' THEN 1 ELSE 0 END AS nullable FROM information_schema.columns \
+                 WHERE table_schema = COALESCE(?, DATABASE()) AND table_name = ?"
+                    .to_owned(),
+                vec![schema.map(Value::from).unwrap_or_default(), name.into()],
+            ),
+            Self::Postgres => (
+                format!(
+                    "SELECT column_name::text AS name, data_type::text AS data_type, \
+                     character_maximum_length::int AS max_length, CASE is_nullable WHEN 'YES' THEN 1 ELSE 0 END AS nullable \
+                     FROM information_schema.columns WHERE table_schema = {} AND table_name = ?",
+                    if schema.is_some() { "?" } else { "current_schema()" }
+                ),
+                schema.map(Value::from).into_iter().chain([name.into()]).collect(),
+            ),
+            Self::Sqlite => (
+                "SELECT name, type AS data_type, NULL AS max_length, 1 - \"notnull\" AS nullable FROM pragma_table_info(?, ?)"
+                    .to_owned(),
+                vec![name.into(), schema.unwrap_or("main").into()],
+            ),
+            Self::Mssql => (
+                "SELECT c.name, t.name AS data_type, CASE WHEN c.max_length = -1 THEN NULL \
+                 WHEN t.name IN ('nchar', 'nvarchar') THEN c.max_length / 2 ELSE c.max_length END AS max_length, \
+                 CAST(c.is_nullable AS INT) AS nullable \
+                 FROM sys.columns c JOIN sys.types t ON t.user_type_id = c.user_type_id WHERE c.object_id = OBJECT_ID(?)"
+                    .to_owned(),
+                vec![table.into()],
+            ),
+            Self::Other => {
+                let err_msg = format!("unsupported driver type, can not inspect table {}. ", table);
+                return Err(Error::UnsupportedDriver(err_msg).into());
+            }
+        };
+        Ok(query)
+    }
+
+    /// Builds a query of the unique constraints and unique indexes of a table but its primary key,
+    /// a row per column with the columns `name` (of the key) and `column_name`.
+    pub fn unique_key_columns_sql(&self, table_name: &str) -> Result<(String, Vec<Value>)> {
+        let table = self.quote_table(table_name)?;
+        let schema = table_name.rsplit_once('.').map(|(schema, _)| schema);
+        let name = unqualified(table_name);
+        let query = match self {
+            Self::Mysql => (
+                "SELECT index_name AS name, column_name FROM information_schema.statistics \
+                 WHERE table_schema = COALESCE(?, DATABASE()) AND table_name = ? AND non_unique = 0 AND index_name <> 'PRIMARY'"
+                    .to_owned(),
+                vec![schema.map(Value::from).unwrap_or_default(), name.into()],
+            ),
+            Self::Postgres => (
+                "SELECT i.indexrelid::regclass::text AS name, a.attname::text AS column_name FROM pg_index i \
+                 JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) \
+                 WHERE i.indrelid = to_regclass(?) AND i.indisunique AND NOT i.indisprimary"
+                    .to_owned(),
+                vec![table.into()],
+            ),
+            Self::Sqlite => (
+                "SELECT l.name, i.name AS column_name FROM pragma_index_list(?, ?) l \
+                 JOIN pragma_index_info(l.name, ?) i WHERE l.\"unique\" = 1 AND l.origin <> 'pk'"
+                    .to_owned(),
+                vec![name.into(), schema.unwrap_or("main").into(), schema.unwrap_or("main").into()],
+            ),
+            Self::Mssql => (
+                "SELECT i.name, c.name AS column_name FROM sys.indexes i \
+                 JOIN sys.index_columns ic ON ic.object_id = i.object_id AND ic.index_id = i.index_id \
+                 JOIN sys.columns c ON c.object_id = ic.object_id AND c.column_id = ic.column_id \
+                 WHERE i.object_id = OBJECT_ID(?) AND i.is_unique = 1 AND i.is_primary_key = 0"
+                    .to_owned(),
+                vec![table.into()],
+            ),
+            Self::Other => {
+                let err_msg = format!("unsupported driver type, can not inspect table {}. ", table);
+                return Err(Error::UnsupportedDriver(err_msg).into());
+            }
+        };
+        Ok(query)
+    }
+
+    /// Builds a `CREATE INDEX` statement, the index is named `idx_{table}_{suffix}` after the unqualified table name.
+    pub fn create_index_sql(&self, table_name: &str, suffix: &str, columns: &[&str]) -> Result<String> {
+        let table = self.quote_table(table_name)?;
+        if *self == Self::Other {
+            let err_msg = format!("unsupported driver type, please create the indexes of {} manually. ", table);
+            return Err(Error::UnsupportedDriver(err_msg).into());
+        }
+        let index = self.quote_ident(&self.index_name(table_name, suffix));
+        Ok(format!("CREATE INDEX {index} ON {table} ({})", columns.join(", ")))
+    }
+
+    /// Builds a query which returns 1 in column `n` when the table has an index named `index`, else 0.
+    pub fn index_exists_sql(&self, table_name: &str, index: &str) -> Result<(String, Vec<Value>)> {
+        let table = self.quote_table(table_name)?;
+        let schema = table_name.rsplit_once('.').map(|(schema, _)| schema);
+        let query = match self {
+            Self::Mysql => (
+                "SELECT COUNT(DISTINCT index_name) AS n FROM information_schema.statistics \
+                 WHERE table_schema = COALESCE(?, DATABASE()) AND table_name = ? AND index_name = ?"
+                    .to_owned(),
+                vec![schema.map(Value::from).unwrap_or_default(), unqualified(table_name).into(), index.into()],
+            ),
+            Self::Postgres => (
+                "SELECT COUNT(*) AS n FROM pg_index i JOIN pg_class c ON c.oid = i.indexrelid \
+                 WHERE i.indrelid = to_regclass(?) AND c.relname = ?"
+                    .to_owned(),
+                vec![table.into(), index.into()],
+            ),
+            Self::Sqlite => (
+                format!(
+                    "SELECT COUNT(*) AS n FROM {}sqlite_master WHERE type = 'index' AND tbl_name = ? AND name = ?",
+                    schema.map(|x| format!("{}.", self.quote_ident(x))).unwrap_or_default()
+                ),
+                vec![unqualified(table_name).into(), index.into()],
+            ),
+            Self::Mssql => (
+                "SELECT COUNT(*) AS n FROM sys.indexes WHERE object_id = OBJECT_ID(?) AND name = ?".to_owned(),
+                vec![table.into(), index.into()],
+            ),
+            Self::Other => {
+                let err_msg = format!("unsupported driver type, can not inspect table {}. ", table);
+                return Err(Error::UnsupportedDriver(err_msg).into());
+            }
+        };
+        Ok(query)
+    }
+
+    /// The name of the index `idx_{table}_{suffix}` of `create_index_sql`, unquoted.
+    pub fn index_name(&self, table_name: &str, suffix: &str) -> String {
+        format!("idx_{}_{suffix}", unqualified(table_name))
+    }
+
+    /// Builds a query which returns 1 in column `n` when the table exists, else 0.
+    pub fn table_exists_sql(&self, table_name: &str) -> Result<(String, Vec<Value>)> {
+        let table = self.quote_table(table_name)?;
+        let schema = table_name.rsplit_once('.').map(|(schema, _)| schema);
+        let name = unqualified(table_name);
+        let query = match self {
+            Self::Mysql => (
+                "SELECT COUNT(*) AS n FROM information_schema.tables WHERE table_schema = COALESCE(?, DATABASE()) AND table_name = ?".to_owned(),
+                vec![schema.map(Value::from).unwrap_or_default(), name.into()],
+            ),
+            Self::Postgres => (
+                "SELECT CASE WHEN to_regclass(?) IS NULL THEN 0 ELSE 1 END AS n".to_owned(),
+                vec![table.into()],
+            ),
+            Self::Sqlite => (
+                format!(
+                    "SELECT COUNT(*) AS n FROM {}sqlite_master WHERE type = 'table' AND name = ?",
+                    schema.map(|x| format!("{}.", self.quote_ident(x))).unwrap_or_default()
+                ),
+                vec![name.into()],
+            ),
+            Self::Mssql => (
+                "SELECT CASE WHEN OBJECT_ID(?, 'U') IS NULL THEN 0 ELSE 1 END AS n".to_owned(),
+                vec![table.into()],
+            ),
+            Self::Other => {
+                let err_msg = format!("unsupported driver type, can not inspect table {}. ", table);
+                return Err(Error::UnsupportedDriver(err_msg).into());
+            }
+        };
+        Ok(query)
+    }
+}
+
+/// Appends `suffix` to a table name, keeping its schema. e.g. `auth.casbin_rule` -> `auth.casbin_rule_schema_version`.
+pub(crate) fn companion_table(table_name: &str, suffix: &str) -> String {
+    format!("{table_name}_{suffix}")
+}
+
+// the table name without its schema.
+fn unqualified(table_name: &str) -> &str {
+    table_name.rsplit('.').next().unwrap_or(table_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_table() {
+        assert_eq!(Dialect::Mysql.quote_table("casbin_rule").unwrap(), "`casbin_rule`");
+        assert_eq!(Dialect::Postgres.quote_table("auth.casbin_rule").unwrap(), "\"auth\".\"casbin_rule\"");
+        assert_eq!(Dialect::Sqlite.quote_table("a\"b").unwrap(), "\"a\"\"b\"");
+        assert_eq!(Dialect::Mssql.quote_table("dbo.rule]s").unwrap(), "[dbo].[rule]]s]");
+        assert_eq!(Dialect::Mysql.quote_table("a`b").unwrap(), "`a``b`");
+        assert!(Dialect::Mysql.quote_table("").is_err());
+        assert!(Dialect::Mysql.quote_table("auth.").is_err());
+    }
+
+    #[test]
+    fn test_create_table_sql() {
+        let columns = Dialect::Postgres.rule_table_columns("auth.svc_a_casbin_rule", 6, false).unwrap();
+        let sql = Dialect::Postgres.create_table_sql("auth.svc_a_casbin_rule", &columns).unwrap();
+        assert!(sql.starts_with("CREATE TABLE IF NOT EXISTS \"auth\".\"svc_a_casbin_rule\" ("));
+        assert!(sql.contains("CONSTRAINT \"unique_key_svc_a_casbin_rule\" UNIQUE(ptype, v0, v1, v2, v3, v4, v5)"));
+
+        let columns = Dialect::Mssql.rule_table_columns("svc_b_casbin_rule", 6, false).unwrap();
+        let sql = Dialect::Mssql.create_table_sql("svc_b_casbin_rule", &columns).unwrap();
+        assert!(sql.starts_with("IF OBJECT_ID(N'[svc_b_casbin_rule]', N'U') IS NULL"));
+        assert!(sql.contains("  v5 NVARCHAR(128) NOT NULL,\n"));
+
+        let columns = Dialect::Mysql.rule_table_columns("casbin_rule", 6, false).unwrap();
+        assert!(columns.last().unwrap().ends_with("UNIQUE(ptype, v0, v1, v2, v3, v4, v5)"));
+        let columns = Dialect::Mysql.rule_table_columns("casbin_rule", 6, true).unwrap();
+        assert_eq!(columns[1], "tenant VARCHAR(64) NOT NULL DEFAULT ''");
+        assert!(columns.last().unwrap().ends_with("UNIQUE(tenant, ptype, v0, v1, v2, v3, v4, v5)"));
+        let columns = Dialect::Postgres.rule_table_columns("casbin_rule", 10, false).unwrap();
+        assert_eq!(columns[11], "v9 VARCHAR NOT NULL");
+        assert!(Dialect::Other.create_table_sql("casbin_rule", &columns).is_err());
+
+        // the unique key covers every value column completely, or the table is not created
+        assert_eq!(Dialect::Mysql.max_arity(), Some(7));
+        let columns = Dialect::Mysql.rule_table_columns("casbin_rule", 7, true).unwrap();
+        assert!(columns.last().unwrap().ends_with("UNIQUE(tenant, ptype, v0, v1, v2, v3, v4, v5, v6)"));
+        let err = Dialect::Mysql.rule_table_columns("casbin_rule", 8, false).unwrap_err();
+        assert!(err.to_string().contains("holds 1036 characters, the database indexes up to 1024"));
+        assert_eq!(Dialect::Mssql.max_arity(), Some(6));
+        assert!(Dialect::Mssql.rule_table_columns("casbin_rule", 6, true).is_ok());
+        assert!(Dialect::Mssql.rule_table_columns("casbin_rule", 7, false).is_err());
+        assert_eq!(Dialect::Sqlite.max_arity(), None);
+    }
+
+    #[test]
+    fn test_create_index_sql() {
+        let sql = Dialect::Postgres.create_index_sql("auth.rules_changes", "revision", &["revision"]).unwrap();
+        assert_eq!(sql, "CREATE INDEX \"idx_rules_changes_revision\" ON \"auth\".\"rules_changes\" (revision)");
+        let sql = Dialect::Mysql.create_index_sql("casbin_rule", "ptype", &["ptype", "v0"]).unwrap();
+        assert_eq!(sql, "CREATE INDEX `idx_casbin_rule_ptype` ON `casbin_rule` (ptype, v0)");
+        assert!(Dialect::Other.create_index_sql("casbin_rule", "ptype", &["ptype"]).is_err());
+    }
+
+    #[test]
+    fn test_alter_table_sql() {
+        let sql = Dialect::Mssql.add_column_sql("casbin_rule", &Dialect::Mssql.tenant_column()).unwrap();
+        assert_eq!(sql, "ALTER TABLE [casbin_rule] ADD tenant NVARCHAR(64) NOT NULL DEFAULT ''");
+        let sql = Dialect::Postgres.add_column_sql("casbin_rule", "tenant VARCHAR").unwrap();
+        assert_eq!(sql, "ALTER TABLE \"casbin_rule\" ADD COLUMN tenant VARCHAR");
+        let sql = Dialect::Mysql.drop_unique_sql("casbin_rule", "unique_key_casbin_adapter").unwrap();
+        assert_eq!(sql, "ALTER TABLE `casbin_rule` DROP INDEX `unique_key_casbin_adapter`");
+        let sql = Dialect::Postgres.drop_unique_sql("casbin_rule", "unique_key_casbin_adapter").unwrap();
+        assert_eq!(sql, "ALTER TABLE \"casbin_rule\" DROP CONSTRAINT \"unique_key_casbin_adapter\"");
+    }
+
+    #[test]
+    fn test_companion_table() {
+        assert_eq!(companion_table("casbin_rule", "schema_version"), "casbin_rule_schema_version");
+        assert_eq!(companion_table("auth.rules", "audit"), "auth.rules_audit");
+    }
+}
@@ -0,0 +1,145 @@
+//! The errors of the adapter.
+//!
+//! They are returned as `casbin::Error::AdapterError`, use [`Error::downcast`] to get them back.
+use std::fmt;
+
+use casbin::error::{AdapterError, Error as CasbinError};
+
+/// An error of the adapter, wrapped in a `casbin::Error::AdapterError`.
+/// ```rust,ignore
+/// match adapter.add_policy("p", "p", rule).await {
+///     Err(err) if Error::downcast(&err).is_some_and(Error::is_duplicate) => {} // already stored
+///     result => result?,
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An added rule is already stored, it violates the unique key of the table.
+    DuplicateRule(rbatis::Error),
+    /// No connection could be acquired from the pool, or the transaction could not be started.
+    Connection(rbatis::Error),
+    /// The transaction could not be committed.
+    Transaction(rbatis::Error),
+    /// A statement failed.
+    Database(rbatis::Error),
+    /// The rules do not fit the table, like a rule with more fields than the table has value columns.
+    SchemaMismatch(String),
+    /// The driver has no sql dialect in the adapter, the statement is not available for it.
+    UnsupportedDriver(String),
+    /// A value of a rule is longer than its column.
+    ValueTooLong {
+        ptype: String,
+        rule: Vec<String>,
+        /// the column of the value, `ptype` or `v{index}`.
+        field: String,
+        len: usize,
+        max: usize,
+    },
+    /// An argument is invalid, like a table name with an empty part.
+    InvalidInput(String),
+}
+
+impl Error {
+    /// The adapter error of a casbin error returned by the adapter.
+    pub fn downcast(err: &CasbinError) -> Option<&Error> {
+        match err {
+            CasbinError::AdapterError(AdapterError(err)) => err.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Whether an added rule is already stored.
+    pub fn is_duplicate(&self) -> bool {
+        matches!(self, Self::DuplicateRule(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateRule(err) => write!(f, "the rule is already stored: {err}"),
+            Self::Connection(err) => write!(f, "connection failure: {err}"),
+            Self::Transaction(err) => write!(f, "transaction failure: {err}"),
+            Self::Database(err) => write!(f, "database error: {err}"),
+            Self::SchemaMismatch(msg) | Self::UnsupportedDriver(msg) | Self::InvalidInput(msg) => f.write_str(msg),
+            Self::ValueTooLong {
+                ptype,
+                rule,
+                field,
+                len,
+                max,
+            } => write!(
+                f,
+                "{field} of rule `{ptype}, {}` has {len} characters, the column holds at most {max}",
+                rule.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::DuplicateRule(err) | Self::Connection(err) | Self::Transaction(err) | Self::Database(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// A failed statement, the unique key violations of the drivers are [`Error::DuplicateRule`].
+impl From<rbatis::Error> for Error {
+    fn from(err: rbatis::Error) -> Self {
+        let msg = err.to_string();
+        // the unique key violations of sqlite, mysql, postgres and mssql
+        let duplicate = [
+            "UNIQUE constraint failed",
+            "Duplicate entry",
+            "duplicate key value violates unique constraint",
+            "Violation of UNIQUE KEY constraint",
+            "Cannot insert duplicate key",
+        ];
+        match duplicate.iter().any(|x| msg.contains(x)) {
+            true => Self::DuplicateRule(err),
+            false => Self::Database(err),
+        }
+    }
+}
+
+impl From<Error> for CasbinError {
+    fn from(err: Error) -> Self {
+        CasbinError::from(AdapterError(Box::new(err)))
+    }
+}
+
+// the casbin errors of the rbatis failures, for `map_err`.
+
+/// A failed statement.
+pub(crate) fn query_error(err: rbatis::Error) -> CasbinError {
+    Error::from(err).into()
+}
+
+/// A failure to acquire a connection or to begin a transaction.
+pub(crate) fn connection_error(err: rbatis::Error) -> CasbinError {
+    Error::Connection(err).into()
+}
+
+/// A failure to commit a transaction.
+pub(crate) fn commit_error(err: rbatis::Error) -> CasbinError {
+    Error::Transaction(err).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downcast() {
+        let err = CasbinError::from(Error::from(rbatis::Error::from("UNIQUE constraint failed: casbin_rule.ptype")));
+        assert!(Error::downcast(&err).is_some_and(Error::is_duplicate));
+        let err = CasbinError::from(Error::from(rbatis::Error::from("no such table: casbin_rule")));
+        assert!(matches!(Error::downcast(&err), Some(Error::Database(_))));
+        let err = CasbinError::from(AdapterError("other".into()));
+        assert!(Error::downcast(&err).is_none());
+    }
+}
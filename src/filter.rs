@@ -0,0 +1,267 @@
+//! Filters of the stored rules, compiled to parameterized sql.
+use casbin::Result;
+use rbs::Value;
+
+use crate::error::Error;
+use crate::models::RuleTable;
+
+/// Selects rules for `RbatisAdapter::load_policy_where` and `RbatisAdapter::remove_where`,
+/// a rule is selected when it matches any entry of the filter.
+/// ```rust,ignore
+/// let filter = PolicyFilter::new()
+///     .section("p", ["alice"])
+///     .ptype_where("g2", [(0, FieldFilter::prefix("data")), (1, FieldFilter::eq("guest").not())]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PolicyFilter {
+    entries: Vec<FilterEntry>,
+}
+
+// the rules of a section or a single ptype, whose fields match all conditions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FilterEntry {
+    ptype: String,
+    section: bool,
+    fields: Vec<(usize, FieldFilter)>,
+}
+
+/// A condition on the `v{index}` column of a rule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldFilter {
+    /// The value equals the string.
+    Eq(String),
+    /// The value is one of the strings, an empty list matches no rule.
+    In(Vec<String>),
+    /// The value starts with the string.
+    Prefix(String),
+    /// The value matches the `LIKE` pattern, `%` matches any string and `_` any character.
+    /// `!` is the escape character on every database, `!%`, `!_` and `!!` match `%`, `_` and `!`.
+    /// Other wildcards of a database, like `[a-z]` on mssql, are not escaped.
+    Like(String),
+    /// The value does not match the condition.
+    Not(Box<FieldFilter>),
+}
+
+impl FieldFilter {
+    pub fn eq(value: impl Into<String>) -> Self {
+        Self::Eq(value.into())
+    }
+
+    pub fn any_of<I: IntoIterator<Item = S>, S: Into<String>>(values: I) -> Self {
+        Self::In(values.into_iter().map(Into::into).collect())
+    }
+
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self::Prefix(prefix.into())
+    }
+
+    /// See [`FieldFilter::Like`], `!` escapes the wildcards.
+    pub fn like(pattern: impl Into<String>) -> Self {
+        Self::Like(pattern.into())
+    }
+
+    /// The negation of the condition.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+
+    // appends the condition on `column` to `sql`, and its values to `args`.
+    fn push_sql(&self, column: &str, sql: &mut String, args: &mut Vec<Value>) {
+        match self {
+            Self::Eq(value) => {
+                sql.push_str(&format!("{column} = ?"));
+                args.push(value.as_str().into());
+            }
+            Self::In(values) if values.is_empty() => sql.push_str("1 = 0"),
+            Self::In(values) => {
+                let params = vec!["?"; values.len()].join(", ");
+                sql.push_str(&format!("{column} IN ({params})"));
+                args.extend(values.iter().map(|x| Value::from(x.as_str())));
+            }
+            Self::Prefix(prefix) => {
+                sql.push_str(&format!("{column} LIKE ? ESCAPE '!'"));
+                args.push(format!("{}%", escape_like(prefix)).into());
+            }
+            Self::Like(pattern) => {
+                sql.push_str(&format!("{column} LIKE ? ESCAPE '!'"));
+                args.push(pattern.as_str().into());
+            }
+            Self::Not(condition) => {
+                sql.push_str("NOT (");
+                condition.push_sql(column, sql, args);
+                sql.push(')');
+            }
+        }
+    }
+}
+
+impl PolicyFilter {
+    /// A filter which matches no rule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches the rules of every ptype of section `sec`, like `g`, `g2` and `g3` for section `g`,
+    /// whose values equal `values` in order. An empty value matches any value.
+    pub fn section<I, S>(self, sec: &str, values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.section_where(sec, exact_values(values))
+    }
+
+    /// Matches the rules of `ptype` only, whose values equal `values` in order. An empty value matches any value.
+    pub fn ptype<I, S>(self, ptype: &str, values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ptype_where(ptype, exact_values(values))
+    }
+
+    /// Matches the rules of every ptype of section `sec` whose fields match all `(index, condition)` pairs.
+    pub fn section_where(mut self, sec: &str, fields: impl IntoIterator<Item = (usize, FieldFilter)>) -> Self {
+        self.entries.push(FilterEntry {
+            ptype: sec.to_owned(),
+            section: true,
+            fields: fields.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Matches the rules of `ptype` whose fields match all `(index, condition)` pairs.
+    pub fn ptype_where(mut self, ptype: &str, fields: impl IntoIterator<Item = (usize, FieldFilter)>) -> Self {
+        self.entries.push(FilterEntry {
+            ptype: ptype.to_owned(),
+            section: false,
+            fields: fields.into_iter().collect(),
+        });
+        self
+    }
+
+    /// The `WHERE` condition of the filter on `table`, scoped to the tenant of the table, and its bind values.
+    pub(crate) fn where_sql(&self, table: &RuleTable) -> Result<(String, Vec<Value>)> {
+        if let Some(index) = self.entries.iter().flat_map(|x| &x.fields).map(|(i, _)| *i).find(|i| *i >= table.arity) {
+            let err_msg = format!("filter on v{index}, the table has {} columns (v0..v{})", table.arity, table.arity - 1);
+            return Err(Error::SchemaMismatch(err_msg).into());
+        }
+        let mut sql = String::new();
+        let mut args = vec![];
+        if let Some(tenant) = &table.tenant {
+            sql.push_str("tenant = ? AND ");
+            args.push(tenant.as_str().into());
+        }
+        if self.entries.is_empty() {
+            sql.push_str("1 = 0");
+            return Ok((sql, args));
+        }
+        sql.push('(');
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                sql.push_str(" OR ");
+            }
+            match entry.section {
+                true => {
+                    sql.push_str("(ptype LIKE ? ESCAPE '!'");
+                    args.push(format!("{}%", escape_like(&entry.ptype)).into());
+                }
+                false => {
+                    sql.push_str("(ptype = ?");
+                    args.push(entry.ptype.as_str().into());
+                }
+            }
+            for (index, condition) in &entry.fields {
+                sql.push_str(" AND ");
+                condition.push_sql(&format!("v{index}"), &mut sql, &mut args);
+            }
+            sql.push(')');
+        }
+        sql.push(')');
+        Ok((sql, args))
+    }
+}
+
+/// casbin's filter matches the rules by section, so `g2` and `p2` rules are filtered like `g` and `p` ones.
+impl From<casbin::Filter<'_>> for PolicyFilter {
+    fn from(f: casbin::Filter<'_>) -> Self {
+        Self::new().section("p", f.p).section("g", f.g)
+    }
+}
+
+// the equality conditions of the non-empty values.
+fn exact_values<I: IntoIterator<Item = S>, S: Into<String>>(values: I) -> Vec<(usize, FieldFilter)> {
+    values
+        .into_iter()
+        .map(Into::into)
+        .enumerate()
+        .filter(|(_, x)| !x.is_empty())
+        .map(|(i, x)| (i, FieldFilter::Eq(x)))
+        .collect()
+}
+
+// escapes the `LIKE` wildcards with `!`, `[` is a wildcard of mssql.
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '!' | '%' | '_' | '[') {
+            escaped.push('!');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::models::{ARITY, BATCH_SIZE};
+
+    fn table(tenant: Option<&str>) -> RuleTable {
+        RuleTable {
+            dialect: Dialect::Postgres,
+            name: "\"casbin_rule\"".to_owned(),
+            arity: ARITY,
+            batch_size: BATCH_SIZE,
+            tracking: None,
+            audit: None,
+            tenant: tenant.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_where_sql() {
+        let filter = PolicyFilter::from(casbin::Filter {
+            p: vec!["", "data1"],
+            g: vec!["alice"],
+        });
+        let (sql, args) = filter.where_sql(&table(None)).unwrap();
+        assert_eq!(sql, "((ptype LIKE ? ESCAPE '!' AND v1 = ?) OR (ptype LIKE ? ESCAPE '!' AND v0 = ?))");
+        assert_eq!(args, vec![Value::from("p%"), "data1".into(), "g%".into(), "alice".into()]);
+
+        let filter = PolicyFilter::new().ptype_where(
+            "g2",
+            [
+                (0, FieldFilter::any_of(["a", "b"])),
+                (1, FieldFilter::prefix("50%_").not()),
+                (2, FieldFilter::In(vec![]).not()),
+            ],
+        );
+        let (sql, args) = filter.where_sql(&table(Some("acme"))).unwrap();
+        assert_eq!(
+            sql,
+            "tenant = ? AND ((ptype = ? AND v0 IN (?, ?) AND NOT (v1 LIKE ? ESCAPE '!') AND NOT (1 = 0)))"
+        );
+        assert_eq!(args, vec![Value::from("acme"), "g2".into(), "a".into(), "b".into(), "50!%!_%".into()]);
+
+        let filter = PolicyFilter::new().ptype_where("p", [(0, FieldFilter::like("50!%%"))]);
+        let (sql, args) = filter.where_sql(&table(None)).unwrap();
+        assert_eq!(sql, "((ptype = ? AND v0 LIKE ? ESCAPE '!'))");
+        assert_eq!(args, vec![Value::from("p"), "50!%%".into()]);
+
+        assert_eq!(PolicyFilter::new().where_sql(&table(None)).unwrap().0, "1 = 0");
+        assert!(PolicyFilter::new().ptype_where("p", [(ARITY, FieldFilter::eq("x"))]).where_sql(&table(None)).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 //! examples:
-//! ```rust no run
+//! ```rust,ignore
 //! let rb = RBatis::new();
 //! rb.init(MysqlDriver {}, url).unwrap();
 //! let adapter = RbatisAdapter::new(&rb).await?;
@@ -11,13 +11,33 @@
 //!
 mod actions;
 mod adapter;
+mod builder;
+mod csv;
+mod ddl;
+mod dialect;
+mod error;
+mod filter;
+mod migrations;
 mod models;
+mod schema;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod transaction;
 mod utils;
+#[cfg(feature = "watcher")]
+mod watcher;
 pub use adapter::RbatisAdapter;
+pub use builder::RbatisAdapterBuilder;
+pub use error::Error;
+pub use filter::{FieldFilter, PolicyFilter};
+pub use models::{AuditEntry, ConflictStrategy, ImportStrategy, PolicyChanges, SaveMode};
+pub use transaction::TxAdapter;
+#[cfg(feature = "watcher")]
+pub use watcher::RbatisWatcher;
 pub use casbin;
 
 /// create a vec of string from arguments
-/// ```rust no run
+/// ```rust,ignore
 /// let args = to_vec!["arg1", "arg2"];
 /// ```
 #[macro_export]
@@ -28,7 +48,7 @@ macro_rules! to_vec {
 }
 
 /// create a vec to string from
-/// ```rust no run
+/// ```rust,ignore
 /// let args = vec_string!["arg1", "arg2"];
 /// ```
 #[macro_export]
@@ -0,0 +1,401 @@
+//! Versioned schema migrations of the rule table.
+//!
+//! The applied versions are recorded in the `{table}_schema_version` table,
+//! every pending migration runs in its own transaction together with its version record.
+//! Note that mysql commits DDL statements implicitly, so a failed migration may be applied partly there.
+use std::collections::BTreeSet;
+
+use casbin::Result;
+use rbatis::{executor::Executor, RBatis};
+
+use crate::actions::finish;
+use crate::ddl::field_index_sql;
+use crate::dialect::{companion_table, Dialect};
+use crate::error::{connection_error, query_error};
+use crate::models::{PTYPE_LEN, VALUE_LEN};
+use crate::schema::Column;
+
+/// The rule table a migration is applied to.
+pub(crate) struct Target<'a> {
+    pub dialect: Dialect,
+    pub table_name: &'a str,
+    pub arity: usize,
+    /// The names of the unique constraints of the rule table before the migration.
+    pub unique_keys: Vec<String>,
+}
+
+/// A schema migration, `statements` builds its sql for the target dialect.
+pub(crate) struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub statements: fn(&Target) -> Result<Vec<String>>,
+}
+
+/// All migrations, ordered by version. New steps are appended, applied ones must not change.
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create the rule table",
+        statements: create_rule_table,
+    },
+    Migration {
+        version: 2,
+        description: "create the revision table",
+        statements: create_revision_table,
+    },
+    Migration {
+        version: 3,
+        description: "create the change log table",
+        statements: create_changes_table,
+    },
+    Migration {
+        version: 4,
+        description: "create the audit table",
+        statements: create_audit_table,
+    },
+    Migration {
+        version: 5,
+        description: "add the tenant column",
+        statements: add_tenant_column,
+    },
+];
+
+fn create_rule_table(t: &Target) -> Result<Vec<String>> {
+    let columns = t.dialect.rule_table_columns(t.table_name, t.arity, false)?;
+    Ok(vec![t.dialect.create_table_sql(t.table_name, &columns)?])
+}
+
+// a single row holding the revision of the rules, see `RbatisAdapter::with_revision_tracking`.
+fn create_revision_table(t: &Target) -> Result<Vec<String>> {
+    let table_name = revision_table(t.table_name);
+    let columns = [
+        "id INT NOT NULL PRIMARY KEY".to_owned(),
+        "revision BIGINT NOT NULL".to_owned(),
+    ];
+    Ok(vec![
+        t.dialect.create_table_sql(&table_name, &columns)?,
+        format!("INSERT INTO {} (id, revision) VALUES (1, 0)", t.dialect.quote_table(&table_name)?),
+    ])
+}
+
+// the rules each revision added and removed, see `RbatisAdapter::load_changes_since`.
+fn create_changes_table(t: &Target) -> Result<Vec<String>> {
+    let table_name = changes_table(t.table_name);
+    let mut columns = vec![
+        t.dialect.id_column().to_owned(),
+        "revision BIGINT NOT NULL".to_owned(),
+        format!("operation {} NOT NULL", t.dialect.varchar(12)),
+        format!("ptype {} NOT NULL", t.dialect.varchar(PTYPE_LEN)),
+    ];
+    columns.extend((0..t.arity).map(|i| format!("v{i} {} NOT NULL", t.dialect.varchar(VALUE_LEN))));
+    Ok(vec![
+        t.dialect.create_table_sql(&table_name, &columns)?,
+        t.dialect.create_index_sql(&table_name, "revision", &["revision"])?,
+    ])
+}
+
+// who added and removed which rules when, see `RbatisAdapter::with_audit`.
+fn create_audit_table(t: &Target) -> Result<Vec<String>> {
+    let mut columns = vec![
+        t.dialect.id_column().to_owned(),
+        format!("operation {} NOT NULL", t.dialect.varchar(12)),
+        format!("actor {}", t.dialect.varchar(255)),
+        format!("created_at {} NOT NULL DEFAULT CURRENT_TIMESTAMP", t.dialect.datetime()),
+        format!("ptype {} NOT NULL", t.dialect.varchar(PTYPE_LEN)),
+    ];
+    columns.extend((0..t.arity).map(|i| format!("v{i} {} NOT NULL", t.dialect.varchar(VALUE_LEN))));
+    Ok(vec![t.dialect.create_table_sql(&audit_table(t.table_name), &columns)?])
+}
+
+// the rules of all tenants share the table, see `RbatisAdapter::for_tenant`.
+// the unique constraint is replaced by one which includes the tenant, sqlite can not
+// alter constraints so the rule table is rebuilt there, keeping the ids.
+fn add_tenant_column(t: &Target) -> Result<Vec<String>> {
+    let tenant = t.dialect.tenant_column();
+    let mut statements = match t.dialect {
+        Dialect::Sqlite => {
+            let values = (0..t.arity).map(|i| format!(", v{i}")).collect::<String>();
+            let columns = t.dialect.rule_table_columns(t.table_name, t.arity, true)?;
+            rebuild_sqlite(
+                t.dialect,
+                t.table_name,
+                &columns,
+                &format!("id, tenant, ptype{values}"),
+                &format!("id, '', ptype{values}"),
+            )?
+        }
+        _ => {
+            let mut statements = vec![t.dialect.add_column_sql(t.table_name, &tenant)?];
+            for name in &t.unique_keys {
+                statements.push(t.dialect.drop_unique_sql(t.table_name, name)?);
+            }
+            statements.push(t.dialect.add_constraint_sql(
+                t.table_name,
+                &t.dialect.unique_constraint(t.table_name, t.arity, true)?,
+            )?);
+            statements
+        }
+    };
+    statements.push(t.dialect.add_column_sql(&changes_table(t.table_name), &tenant)?);
+    statements.push(t.dialect.add_column_sql(&audit_table(t.table_name), &tenant)?);
+    Ok(statements)
+}
+
+// rebuilds the sqlite rule table with `columns`, as sqlite can not alter constraints, keeping the ids.
+// the `insert` columns of the new table are filled with `select` from the old one.
+fn rebuild_sqlite(dialect: Dialect, table_name: &str, columns: &[String], insert: &str, select: &str) -> Result<Vec<String>> {
+    let rebuilt = companion_table(table_name, "rebuild");
+    let new_name = table_name.rsplit('.').next().unwrap_or(table_name);
+    Ok(vec![
+        // left over by an earlier run which failed
+        format!("DROP TABLE IF EXISTS {}", dialect.quote_table(&rebuilt)?),
+        dialect.create_table_sql(&rebuilt, columns)?,
+        format!(
+            "INSERT INTO {} ({insert}) SELECT {select} FROM {}",
+            dialect.quote_table(&rebuilt)?,
+            dialect.quote_table(table_name)?
+        ),
+        format!("DROP TABLE {}", dialect.quote_table(table_name)?),
+        dialect.rename_table_sql(&rebuilt, new_name)?,
+    ])
+}
+
+/// Adds the value columns below `arity` which the existing rule table and its change and audit logs miss,
+/// so that raising the arity of a deployment widens its tables. The unique key of the rule table is
+/// rebuilt over the new columns, the existing rows get empty values. Returns the added columns of the rule table.
+pub(crate) async fn add_value_columns(rb: &RBatis, table_name: &str, arity: usize) -> Result<Vec<usize>> {
+    let dialect = Dialect::of(rb)?;
+    let mut statements = vec![];
+    for log in [changes_table(table_name), audit_table(table_name)] {
+        let columns = table_columns(rb, dialect, &log).await?;
+        for i in missing_values(&columns, arity) {
+            statements.push(dialect.add_column_sql(&log, &value_column(dialect, i))?);
+        }
+    }
+
+    let columns = table_columns(rb, dialect, table_name).await?;
+    let missing = missing_values(&columns, arity);
+    if !missing.is_empty() {
+        let tenant = columns.contains("tenant");
+        // the value columns above `arity` are kept
+        let width = columns
+            .iter()
+            .filter_map(|x| x.strip_prefix('v')?.parse::<usize>().ok())
+            .map(|i| i + 1)
+            .fold(arity, usize::max);
+        match dialect {
+            Dialect::Sqlite => {
+                let fixed = if tenant { "id, tenant, ptype" } else { "id, ptype" };
+                let names = (0..width).map(|i| format!(", v{i}")).collect::<String>();
+                let values = (0..width)
+                    .map(|i| match missing.contains(&i) {
+                        true => ", ''".to_owned(),
+                        false => format!(", v{i}"),
+                    })
+                    .collect::<String>();
+                let definitions = dialect.rule_table_columns(table_name, width, tenant)?;
+                statements.extend(rebuild_sqlite(
+                    dialect,
+                    table_name,
+                    &definitions,
+                    &format!("{fixed}{names}"),
+                    &format!("{fixed}{values}"),
+                )?);
+            }
+            _ => {
+                for i in &missing {
+                    statements.push(dialect.add_column_sql(table_name, &value_column(dialect, *i))?);
+                }
+                for name in unique_keys(rb, dialect, table_name).await? {
+                    statements.push(dialect.drop_unique_sql(table_name, &name)?);
+                }
+                let unique = dialect.unique_constraint(table_name, width, tenant)?;
+                statements.push(dialect.add_constraint_sql(table_name, &unique)?);
+            }
+        }
+    }
+    if statements.is_empty() {
+        return Ok(missing);
+    }
+    let tx = rb
+        .acquire_begin()
+        .await
+        .map_err(connection_error)?;
+    let result = exec_all(&tx, statements).await;
+    finish(tx, result).await?;
+    Ok(missing)
+}
+
+// the lowercase column names of a table, empty when it does not exist.
+pub(crate) async fn table_columns(rb: &RBatis, dialect: Dialect, table_name: &str) -> Result<BTreeSet<String>> {
+    let (sql, args) = dialect.columns_sql(table_name)?;
+    let columns: Vec<Column> = rb
+        .query_decode(&sql, args)
+        .await
+        .map_err(query_error)?;
+    Ok(columns.into_iter().map(|x| x.name.to_lowercase()).collect())
+}
+
+// the value columns below `arity` an existing table misses.
+fn missing_values(columns: &BTreeSet<String>, arity: usize) -> Vec<usize> {
+    if columns.is_empty() {
+        return vec![];
+    }
+    (0..arity).filter(|i| !columns.contains(&format!("v{i}"))).collect()
+}
+
+// a value column added to a table with rows, they get an empty value.
+fn value_column(dialect: Dialect, i: usize) -> String {
+    format!("v{i} {} NOT NULL DEFAULT ''", dialect.varchar(VALUE_LEN))
+}
+
+async fn exec_all(tx: &dyn Executor, statements: Vec<String>) -> Result<()> {
+    for sql in statements {
+        tx.exec(&sql, vec![])
+            .await
+            .map_err(query_error)?;
+    }
+    Ok(())
+}
+
+/// The table which holds the revision of the rules.
+pub(crate) fn revision_table(table_name: &str) -> String {
+    companion_table(table_name, "revision")
+}
+
+/// The change log of the rules.
+pub(crate) fn changes_table(table_name: &str) -> String {
+    companion_table(table_name, "changes")
+}
+
+/// The audit log of the rules.
+pub(crate) fn audit_table(table_name: &str) -> String {
+    companion_table(table_name, "audit")
+}
+
+// the table which records the applied versions.
+fn version_table(table_name: &str) -> String {
+    companion_table(table_name, "schema_version")
+}
+
+async fn create_version_table(rb: &RBatis, dialect: Dialect, table_name: &str) -> Result<()> {
+    let columns = [
+        "version INT NOT NULL PRIMARY KEY".to_owned(),
+        format!("description {} NOT NULL", dialect.varchar(255)),
+    ];
+    let sql = dialect.create_table_sql(&version_table(table_name), &columns)?;
+    rb.exec(&sql, vec![])
+        .await
+        .map_err(query_error)?;
+    Ok(())
+}
+
+/// The latest applied version, 0 when the table was never migrated.
+pub(crate) async fn schema_version(rb: &RBatis, table_name: &str) -> Result<i32> {
+    let dialect = Dialect::of(rb)?;
+    let version_table = version_table(table_name);
+    let (sql, args) = dialect.table_exists_sql(&version_table)?;
+    let exists: i64 = rb
+        .query_decode(&sql, args)
+        .await
+        .map_err(query_error)?;
+    if exists == 0 {
+        return Ok(0);
+    }
+    let sql = format!(
+        "SELECT COALESCE(MAX(version), 0) AS version FROM {}",
+        dialect.quote_table(&version_table)?
+    );
+    rb.query_decode(&sql, vec![])
+        .await
+        .map_err(query_error)
+}
+
+// the names of the unique constraints of the rule table.
+async fn unique_keys(rb: &RBatis, dialect: Dialect, table_name: &str) -> Result<Vec<String>> {
+    #[derive(serde::Deserialize)]
+    struct Key {
+        name: String,
+    }
+    let (sql, args) = dialect.unique_keys_sql(table_name)?;
+    let keys: Vec<Key> = rb
+        .query_decode(&sql, args)
+        .await
+        .map_err(query_error)?;
+    Ok(keys.into_iter().map(|x| x.name).collect())
+}
+
+/// Creates the missing indexes on the value columns `fields` of the rule table, see `ddl::field_index_sql`.
+/// They are created after the migrations, which may rebuild the table. Returns the fields of the created indexes.
+pub(crate) async fn create_field_indexes(rb: &RBatis, table_name: &str, fields: &BTreeSet<usize>) -> Result<Vec<usize>> {
+    let dialect = Dialect::of(rb)?;
+    let mut created = vec![];
+    for field in fields {
+        let index = dialect.index_name(table_name, &format!("v{field}"));
+        let (sql, args) = dialect.index_exists_sql(table_name, &index)?;
+        let exists: i64 = rb
+            .query_decode(&sql, args)
+            .await
+            .map_err(query_error)?;
+        if exists == 0 {
+            rb.exec(&field_index_sql(dialect, table_name, *field)?, vec![])
+                .await
+                .map_err(query_error)?;
+            created.push(*field);
+        }
+    }
+    Ok(created)
+}
+
+/// Applies the pending migrations in order, returns the applied versions.
+/// The existing tables are widened to `arity` first, see `add_value_columns`.
+pub(crate) async fn migrate(rb: &RBatis, table_name: &str, arity: usize) -> Result<Vec<i32>> {
+    let dialect = Dialect::of(rb)?;
+    add_value_columns(rb, table_name, arity).await?;
+    create_version_table(rb, dialect, table_name).await?;
+    let current = schema_version(rb, table_name).await?;
+    let mut target = Target {
+        dialect,
+        table_name,
+        arity,
+        unique_keys: vec![],
+    };
+
+    let mut applied = vec![];
+    for migration in MIGRATIONS.iter().filter(|x| x.version > current) {
+        target.unique_keys = unique_keys(rb, dialect, table_name).await?;
+        let statements = (migration.statements)(&target)?;
+        let tx = rb
+            .acquire_begin()
+            .await
+            .map_err(connection_error)?;
+        let result = apply(&tx, &target, migration, statements).await;
+        finish(tx, result).await?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+async fn apply(tx: &dyn Executor, target: &Target<'_>, migration: &Migration, statements: Vec<String>) -> Result<()> {
+    exec_all(tx, statements).await?;
+    let sql = format!(
+        "INSERT INTO {} (version, description) VALUES (?, ?)",
+        target.dialect.quote_table(&version_table(target.table_name))?
+    );
+    tx.exec(&sql, vec![migration.version.into(), migration.description.into()])
+        .await
+        .map_err(query_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_order() {
+        let versions = MIGRATIONS.iter().map(|x| x.version).collect::<Vec<_>>();
+        let expected = (1..=MIGRATIONS.len() as i32).collect::<Vec<_>>();
+        assert_eq!(versions, expected);
+    }
+}
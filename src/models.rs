@@ -1,80 +1,491 @@
-use serde::{Deserialize, Serialize};
+use rbs::Value;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 
+use crate::dialect::Dialect;
+use crate::error::Error;
+
+/// the default table name of the rules.
 pub const TABLE_NAME: &str = "casbin_rule";
 
+/// the default number of `v*` columns of the rule table.
+pub const ARITY: usize = 6;
+
+/// the length of the `ptype` column.
+pub(crate) const PTYPE_LEN: usize = 12;
+
+/// the length of the `v*` columns.
+pub(crate) const VALUE_LEN: usize = 128;
+
+/// the length of the `tenant` column.
+pub(crate) const TENANT_LEN: usize = 64;
+
+/// The lengths of the `ptype` and `v*` columns, the longer values are rejected before they are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ColumnLengths {
+    pub ptype: usize,
+    pub value: usize,
+}
+
+impl Default for ColumnLengths {
+    fn default() -> Self {
+        Self {
+            ptype: PTYPE_LEN,
+            value: VALUE_LEN,
+        }
+    }
+}
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A row of the rule table, `values` holds the `v0..vN` columns in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
 pub struct CasbinRule {
     pub id: Option<i32>,
-    pub ptype: Option<String>,
-    pub v0: Option<String>,
-    pub v1: Option<String>,
-    pub v2: Option<String>,
-    pub v3: Option<String>,
-    pub v4: Option<String>,
-    pub v5: Option<String>,
-}
-
-rbatis::impl_select!(CasbinRule {}, TABLE_NAME);
-rbatis::impl_insert!(CasbinRule {}, TABLE_NAME);
-rbatis::impl_delete!(CasbinRule {delete_all()=>" "}, TABLE_NAME);
-rbatis::impl_delete!(CasbinRule {delete_policy(ptype: &str, rules: &[String]) => 
-    "`where ptype = #{ptype} `
-    `AND v0 = #{rules[0]} AND v1 = #{rules[1]} `
-    `AND v2 = #{rules[2]} AND v3 = #{rules[3]} ` 
-    `AND v4 = #{rules[4]} AND v5 = #{rules[5]} `"}, TABLE_NAME);
+    pub ptype: String,
+    pub values: Vec<String>,
+}
 
-rbatis::impl_delete!(CasbinRule {delete_filtered_policy_5(ptype: &str, rules: &[Option<String>]) => 
-    "`where ptype = #{ptype} `
-    `AND (v5 is NULL OR v5 = COALESCE(#{rules[0]},v5)) `"}, TABLE_NAME);
+// rows are decoded from a column map, so the number of `v*` columns is not fixed.
+impl<'de> Deserialize<'de> for CasbinRule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let columns = BTreeMap::<String, Value>::deserialize(deserializer)?;
+        let mut rule = CasbinRule::default();
+        let mut values = vec![];
+        for (column, value) in columns {
+            let value = match value {
+                Value::Ext(_, v) => *v,
+                v => v,
+            };
+            match column.as_str() {
+                "id" => rule.id = value.as_i64().map(|x| x as i32),
+                "ptype" => rule.ptype = value.into_string().unwrap_or_default(),
+                _ => {
+                    if let Some(Ok(index)) = column.strip_prefix('v').map(str::parse::<usize>) {
+                        values.push((index, value.into_string().unwrap_or_default()));
+                    }
+                }
+            }
+        }
+        let len = values.iter().map(|(index, _)| index + 1).max().unwrap_or_default();
+        rule.values = vec![String::new(); len];
+        for (index, v) in values {
+            rule.values[index] = v;
+        }
+        Ok(rule)
+    }
+}
 
-rbatis::impl_delete!(CasbinRule {delete_filtered_policy_4(ptype: &str, rules: &[Option<String>]) => 
-    "`where ptype = #{ptype} `
-    `AND (v4 is NULL OR v4 = COALESCE(#{rules[0]},v4)) ` 
-    `AND (v5 is NULL OR v5 = COALESCE(#{rules[1]},v5)) `"}, TABLE_NAME);
+/// A row of the change log, `operation` is one of the `CHANGE_*` constants.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct ChangeRecord {
+    pub revision: i64,
+    pub operation: String,
+    /// the tenant of the write, empty without a tenant.
+    #[serde(default)]
+    pub tenant: String,
+    #[serde(flatten)]
+    pub rule: CasbinRule,
+}
 
-rbatis::impl_delete!(CasbinRule {delete_filtered_policy_3(ptype: &str, rules: &[Option<String>]) => 
-    "`where ptype = #{ptype} `
-    `AND (v3 is NULL OR v3 = COALESCE(#{rules[0]},v3)) `
-    `AND (v4 is NULL OR v4 = COALESCE(#{rules[1]},v4)) `
-    `AND (v5 is NULL OR v5 = COALESCE(#{rules[2]},v5)) `"}, TABLE_NAME);
+/// An entry of the audit log, see `RbatisAdapter::audit_log`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// `add`, `remove`, or `clear` when all rules were removed.
+    pub operation: String,
+    pub actor: Option<String>,
+    pub created_at: rbatis::rbdc::DateTime,
+    pub ptype: String,
+    pub rule: Vec<String>,
+}
+
+/// A row of the audit log.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct AuditRecord {
+    pub id: i64,
+    pub operation: String,
+    pub actor: Option<String>,
+    pub created_at: rbatis::rbdc::DateTime,
+    #[serde(flatten)]
+    pub rule: CasbinRule,
+}
+
+/// All rules were removed.
+pub(crate) const AUDIT_CLEAR: &str = "clear";
+
+/// The rule was added.
+pub(crate) const CHANGE_ADD: &str = "add";
+/// The rule was removed.
+pub(crate) const CHANGE_REMOVE: &str = "remove";
+/// All rules were replaced, the rule of the record is empty.
+pub(crate) const CHANGE_RESET: &str = "reset";
+
+/// The default maximum number of rows of one insert statement.
+pub const BATCH_SIZE: usize = 1000;
+
+/// The default number of rows `load_policy` reads per query.
+pub const PAGE_SIZE: usize = 10000;
+
+/// How `save_policy` writes the rules of the model.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaveMode {
+    /// Deletes every stored rule and inserts the rules of the model.
+    #[default]
+    Replace,
+    /// Inserts the missing rules and deletes the stale ones, unchanged rows keep their ids.
+    Diff,
+}
+
+/// The number of rows a `save_policy` added and removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PolicyChanges {
+    pub added: u64,
+    pub removed: u64,
+}
+
+/// What `add_policy` and `add_policies` do with a rule which is already stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    /// A stored rule is an error, `add_policies` rolls back the whole batch.
+    #[default]
+    Error,
+    /// Stored rules are skipped, the add methods return `true` as the rules are stored either way.
+    Skip,
+    /// Stored rules are skipped, the add methods return `true` only when a rule was inserted.
+    Report,
+}
 
-rbatis::impl_delete!(CasbinRule {delete_filtered_policy_2(ptype: &str, rules: &[Option<String>]) => 
+/// What `import_csv` does with the rules of the file and the stored rules.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImportStrategy {
+    /// The rules of the file replace every stored rule, with the save mode of the adapter.
+    #[default]
+    Replace,
+    /// The rules of the file are added, the stored ones are skipped.
+    Merge,
+    /// The rules of the file are added, a stored rule is an error and nothing is imported.
+    Fail,
+}
+
+/// The rule table the queries run against.
+#[derive(Clone, Debug)]
+pub(crate) struct RuleTable {
+    pub dialect: Dialect,
+    /// the quoted table name, see `Dialect::quote_table`.
+    pub name: String,
+    /// the number of `v*` columns.
+    pub arity: usize,
+    /// the maximum number of rows of one insert statement.
+    pub batch_size: usize,
+    /// the revision tables, when the writes are tracked.
+    pub tracking: Option<Tracking>,
+    /// the audit table, when the writes are audited.
+    pub audit: Option<Audit>,
+    /// the tenant every query is scoped to, see `RbatisAdapter::for_tenant`.
+    pub tenant: Option<String>,
+}
+
+/// The quoted audit table of a rule table, and the actor of the writes.
+#[derive(Clone, Debug)]
+pub(crate) struct Audit {
+    pub table: String,
+    pub actor: Option<String>,
+}
+
+/// The quoted tables which track the writes of a rule table.
+#[derive(Clone, Debug)]
+pub(crate) struct Tracking {
+    /// a single row holding the revision, every write which changes the rules bumps it.
+    pub revision_table: String,
+    /// the rules each revision added and removed.
+    pub changes_table: String,
+}
+
+impl RuleTable {
+    /// Whether the writes collect the rules they change, for the change log or the audit log.
+    pub fn logs_changes(&self) -> bool {
+        self.tracking.is_some() || self.audit.is_some()
+    }
+
+    /// The `v0, v1, ...` column list.
+    pub fn value_columns(&self) -> String {
+        (0..self.arity).map(|i| format!("v{i}")).collect::<Vec<_>>().join(", ")
+    }
+
+    /// The `tenant, ptype, v0, ...` column list of a rule, the tenant only when the table is scoped to one.
+    pub fn rule_columns(&self) -> String {
+        match self.tenant {
+            Some(_) => format!("tenant, ptype, {}", self.value_columns()),
+            None => format!("ptype, {}", self.value_columns()),
+        }
+    }
+
+    /// The statement around the `([tenant, ]ptype, v0, ..)` value rows of an insert, as `(head, tail)`.
+    /// With `skip_stored` the rows which are already stored are skipped instead of failing.
+    pub fn insert_sql(&self, skip_stored: bool) -> casbin::Result<(String, String)> {
+        let columns = self.rule_columns();
+        if !skip_stored {
+            return Ok((format!("INSERT INTO {} ({columns}) VALUES ", self.name), String::new()));
+        }
+        let sql = match self.dialect {
+            Dialect::Mysql => (format!("INSERT IGNORE INTO {} ({columns}) VALUES ", self.name), String::new()),
+            Dialect::Postgres | Dialect::Sqlite => (
+                format!("INSERT INTO {} ({columns}) VALUES ", self.name),
+                " ON CONFLICT DO NOTHING".to_owned(),
+            ),
+            Dialect::Mssql => {
+                let on = columns
+                    .split(", ")
+                    .map(|x| format!("t.{x} = s.{x}"))
+                    .collect::<Vec<_>>()
+                    .join(" AND ");
+                let source = columns.split(", ").map(|x| format!("s.{x}")).collect::<Vec<_>>().join(", ");
+                (
+                    format!("MERGE INTO {} AS t USING (VALUES ", self.name),
+                    format!(") AS s ({columns}) ON {on} WHEN NOT MATCHED THEN INSERT ({columns}) VALUES ({source});"),
+                )
+            }
+            Dialect::Other => {
+                let err_msg = format!("unsupported driver type, can not skip the stored rules of {}. ", self.name);
+                return Err(Error::UnsupportedDriver(err_msg).into());
+            }
+        };
+        Ok(sql)
+    }
+
+    /// The rows of one insert statement, it is bounded by the bind parameter limit of the driver.
+    pub fn insert_chunk_size(&self) -> usize {
+        self.chunk_size(self.arity + 1)
+    }
+
+    /// The rows of one insert statement with `params_per_row` bind parameters per row, besides the tenant.
+    pub fn chunk_size(&self, params_per_row: usize) -> usize {
+        let params_per_row = params_per_row + usize::from(self.tenant.is_some());
+        (self.dialect.max_bind_params() / params_per_row).min(self.batch_size).max(1)
+    }
+}
+
+// the `table` argument of every query is the quoted table name,
+// and the `tenant` argument scopes it to the rules of a tenant when it is not `None`.
+impl CasbinRule {
+    /// Inserts the rules with one multi-row statement, see `RuleTable::insert_sql` and `RuleTable::insert_chunk_size`.
+    #[rbatis::py_sql(
+        "`${head}`
+        trim ',':
+            for _,rule in rules:
+                `(`
+                if tenant != null:
+                    `#{tenant}, `
+                `#{rule.ptype}`
+                for _,v in rule.values:
+                    `, #{v}`
+                `),`
+        `${tail}`"
+    )]
+    pub async fn insert_batch(
+        rb: &dyn rbatis::executor::Executor,
+        head: &str,
+        tail: &str,
+        tenant: Option<&str>,
+        rules: &[CasbinRule],
+    ) -> Result<rbatis::rbdc::db::ExecResult, rbatis::Error> {
+        impled!()
+    }
+
+    /// Logs the rules of a revision to the change log, `head` is the insert statement up to `VALUES`.
+    #[rbatis::py_sql(
+        "`${head}`
+        trim ',':
+            for _,rule in rules:
+                `(#{revision}, #{operation}, `
+                if tenant != null:
+                    `#{tenant}, `
+                `#{rule.ptype}`
+                for _,v in rule.values:
+                    `, #{v}`
+                `),`"
+    )]
+    pub async fn insert_changes(
+        rb: &dyn rbatis::executor::Executor,
+        head: &str,
+        revision: i64,
+        operation: &str,
+        tenant: Option<&str>,
+        rules: &[CasbinRule],
+    ) -> Result<rbatis::rbdc::db::ExecResult, rbatis::Error> {
+        impled!()
+    }
+
+    /// Logs the rules of a write to the audit log, `head` is the insert statement up to `VALUES`.
+    #[rbatis::py_sql(
+        "`${head}`
+        trim ',':
+            for _,rule in rules:
+                `(#{operation}, #{actor}, `
+                if tenant != null:
+                    `#{tenant}, `
+                `#{rule.ptype}`
+                for _,v in rule.values:
+                    `, #{v}`
+                `),`"
+    )]
+    pub async fn insert_audit(
+        rb: &dyn rbatis::executor::Executor,
+        head: &str,
+        operation: &str,
+        actor: Option<&str>,
+        tenant: Option<&str>,
+        rules: &[CasbinRule],
+    ) -> Result<rbatis::rbdc::db::ExecResult, rbatis::Error> {
+        impled!()
+    }
+
+    /// Sets the values of the rule of `ptype` with `old_values` to `new_values`.
+    #[rbatis::py_sql(
+        "`update ${table} set `
+        trim ',':
+            for k,v in new_values:
+                `v${k} = #{v},`
+        ` where ptype = #{ptype}`
+        if tenant != null:
+            ` and tenant = #{tenant}`
+        for k,v in old_values:
+            ` and v${k} = #{v}`"
+    )]
+    pub async fn update_values(
+        rb: &dyn rbatis::executor::Executor,
+        table: &str,
+        tenant: Option<&str>,
+        ptype: &str,
+        old_values: &[String],
+        new_values: &[String],
+    ) -> Result<rbatis::rbdc::db::ExecResult, rbatis::Error> {
+        impled!()
+    }
+}
+
+rbatis::impl_select!(CasbinRule {select_all(table: &str, tenant: Option<&str>) =>
+    "`  `
+    if tenant != null:
+        `where tenant = #{tenant}`"}, table);
+rbatis::impl_delete!(CasbinRule {delete_all(table: &str, tenant: Option<&str>) =>
+    "`  `
+    if tenant != null:
+        `where tenant = #{tenant}`"}, table);
+rbatis::impl_delete!(CasbinRule {delete_by_ids(table: &str, tenant: Option<&str>, ids: &[i32]) =>
+    "`where id in (`
+    trim ',':
+        for _,id in ids:
+            `#{id},`
+    `) `
+    if tenant != null:
+        `AND tenant = #{tenant}`"}, table);
+rbatis::impl_delete!(CasbinRule {delete_policy(table: &str, tenant: Option<&str>, ptype: &str, rules: &[String]) =>
     "`where ptype = #{ptype} `
-    `AND (v2 is NULL OR v2 = COALESCE(#{rules[0]},v2)) `
-    `AND (v3 is NULL OR v3 = COALESCE(#{rules[1]},v3)) `
-    `AND (v4 is NULL OR v4 = COALESCE(#{rules[2]},v4)) `
-    `AND (v5 is NULL OR v5 = COALESCE(#{rules[3]},v5)) `"}, TABLE_NAME);
+    if tenant != null:
+        `AND tenant = #{tenant} `
+    for k,v in rules:
+        `AND v${k} = #{v} `"}, table);
 
-rbatis::impl_delete!(CasbinRule {delete_filtered_policy_1(ptype: &str, rules: &[Option<String>]) => 
+// `rules` has one entry per column, the columns of the `None` entries are not filtered.
+rbatis::impl_delete!(CasbinRule {delete_filtered_policy(table: &str, tenant: Option<&str>, ptype: &str, rules: &[Option<String>]) =>
     "`where ptype = #{ptype} `
-    `AND (v1 is NULL OR v1 = COALESCE(#{rules[0]},v1)) `
-    `AND (v2 is NULL OR v2 = COALESCE(#{rules[1]},v2)) `
-    `AND (v3 is NULL OR v3 = COALESCE(#{rules[2]},v3)) `
-    `AND (v4 is NULL OR v4 = COALESCE(#{rules[3]},v4)) `
-    `AND (v5 is NULL OR v5 = COALESCE(#{rules[4]},v5)) `"}, TABLE_NAME);
+    if tenant != null:
+        `AND tenant = #{tenant} `
+    for k,v in rules:
+        if v != null:
+            `AND v${k} = #{v} `"}, table);
 
-rbatis::impl_delete!(CasbinRule {delete_filtered_policy_0(ptype: &str, rules: &[Option<String>]) => 
+rbatis::impl_select!(CasbinRule {select_by_filter(table: &str, tenant: Option<&str>, ptype: &str, rules: &[Option<String>]) =>
     "`where ptype = #{ptype} `
-    `AND (v0 is NULL OR v0 = COALESCE(#{rules[0]},v0)) `
-    `AND (v1 is NULL OR v1 = COALESCE(#{rules[1]},v1)) `
-    `AND (v2 is NULL OR v2 = COALESCE(#{rules[2]},v2)) `
-    `AND (v3 is NULL OR v3 = COALESCE(#{rules[3]},v3)) `
-    `AND (v4 is NULL OR v4 = COALESCE(#{rules[4]},v4)) `
-    `AND (v5 is NULL OR v5 = COALESCE(#{rules[5]},v5)) `"}, TABLE_NAME);
-
-    // "SELECT * from  casbin_rule WHERE (
-    //     ptype LIKE 'g%' AND v0 LIKE ? AND v1 LIKE ? AND v2 LIKE ? AND v3 LIKE ? AND v4 LIKE ? AND v5 LIKE ? )
-    // OR (
-    //     ptype LIKE 'p%' AND v0 LIKE ? AND v1 LIKE ? AND v2 LIKE ? AND v3 LIKE ? AND v4 LIKE ? AND v5 LIKE ? );"
-rbatis::impl_select!(CasbinRule {select_filtered_policy(g_values: Vec<&str>, p_values: Vec<&str>) => 
-    "`where (ptype ='g' `
-    for k,val in g_values:
-        if val != '':
-            `and v${k} = #{val} `
-    `) or (ptype = 'p' `
-    for k,val in p_values:
-        if val != '':
-            `and v${k} = #{val} `
-    `)`"}, TABLE_NAME);
+    if tenant != null:
+        `AND tenant = #{tenant} `
+    for k,v in rules:
+        if v != null:
+            `AND v${k} = #{v} `"}, table);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_vec;
 
+    #[test]
+    fn test_insert_chunk_size() {
+        let mut table = RuleTable {
+            dialect: Dialect::Mssql,
+            name: "[casbin_rule]".to_owned(),
+            arity: 6,
+            batch_size: BATCH_SIZE,
+            tracking: None,
+            audit: None,
+            tenant: None,
+        };
+        assert_eq!(table.insert_chunk_size(), 300);
+        table.arity = 2100;
+        assert_eq!(table.insert_chunk_size(), 1);
+        table.dialect = Dialect::Postgres;
+        table.arity = 6;
+        assert_eq!(table.insert_chunk_size(), BATCH_SIZE);
+        table.batch_size = 0;
+        assert_eq!(table.insert_chunk_size(), 1);
+        table.dialect = Dialect::Mssql;
+        table.batch_size = BATCH_SIZE;
+        table.tenant = Some("acme".to_owned());
+        assert_eq!(table.insert_chunk_size(), 262);
+    }
+
+    #[test]
+    fn test_insert_sql() {
+        let mut table = RuleTable {
+            dialect: Dialect::Mysql,
+            name: "`casbin_rule`".to_owned(),
+            arity: 2,
+            batch_size: BATCH_SIZE,
+            tracking: None,
+            audit: None,
+            tenant: None,
+        };
+        let (head, tail) = table.insert_sql(false).unwrap();
+        assert_eq!(head, "INSERT INTO `casbin_rule` (ptype, v0, v1) VALUES ");
+        assert!(tail.is_empty());
+        assert!(table.insert_sql(true).unwrap().0.starts_with("INSERT IGNORE INTO"));
+
+        table.dialect = Dialect::Sqlite;
+        assert_eq!(table.insert_sql(true).unwrap().1, " ON CONFLICT DO NOTHING");
+
+        table.dialect = Dialect::Mssql;
+        table.name = "[casbin_rule]".to_owned();
+        let (head, tail) = table.insert_sql(true).unwrap();
+        assert_eq!(head, "MERGE INTO [casbin_rule] AS t USING (VALUES ");
+        assert_eq!(
+            tail,
+            ") AS s (ptype, v0, v1) ON t.ptype = s.ptype AND t.v0 = s.v0 AND t.v1 = s.v1 \
+             WHEN NOT MATCHED THEN INSERT (ptype, v0, v1) VALUES (s.ptype, s.v0, s.v1);"
+        );
+
+        table.tenant = Some("acme".to_owned());
+        let (head, tail) = table.insert_sql(true).unwrap();
+        assert_eq!(head, "MERGE INTO [casbin_rule] AS t USING (VALUES ");
+        assert!(tail.starts_with(") AS s (tenant, ptype, v0, v1) ON t.tenant = s.tenant AND t.ptype = s.ptype"));
+        table.tenant = None;
+
+        table.dialect = Dialect::Other;
+        assert!(table.insert_sql(false).is_ok());
+        assert!(table.insert_sql(true).is_err());
+    }
+
+    #[test]
+    fn test_decode_casbin_rule() {
+        let row = rbs::value! {
+            "v1": "data1",
+            "v0": "alice",
+            "ptype": "p",
+            "id": 1,
+            "v10": "",
+            "v2": "read",
+        };
+        let rule: CasbinRule = rbatis::decode(Value::Array(vec![row])).unwrap();
+        assert_eq!(rule.id, Some(1));
+        assert_eq!(rule.ptype, "p");
+        assert_eq!(rule.values, to_vec!["alice", "data1", "read", "", "", "", "", "", "", "", ""]);
+    }
+}
@@ -0,0 +1,156 @@
+//! Checks a rule table against the columns and the unique key the adapter expects,
+//! for the tables created by other tools.
+use std::collections::{BTreeMap, BTreeSet};
+
+use casbin::Result;
+use rbatis::RBatis;
+use serde::Deserialize;
+
+use crate::dialect::Dialect;
+use crate::error::{query_error, Error};
+use crate::models::{ColumnLengths, TENANT_LEN};
+
+/// A column of the table, see `Dialect::columns_sql`.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Column {
+    pub name: String,
+    pub data_type: String,
+    pub max_length: Option<i64>,
+    pub nullable: i64,
+}
+
+/// A column of a unique key of the table, see `Dialect::unique_key_columns_sql`.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct KeyColumn {
+    pub name: String,
+    pub column_name: String,
+}
+
+/// Fails with [`Error::SchemaMismatch`] listing every problem of the table `table_name`,
+/// `tenant` requires the `tenant` column. Returns whether the table has the `tenant` column.
+pub(crate) async fn verify(rb: &RBatis, table_name: &str, arity: usize, lengths: ColumnLengths, tenant: bool) -> Result<bool> {
+    let dialect = Dialect::of(rb)?;
+    let (sql, args) = dialect.columns_sql(table_name)?;
+    let columns: Vec<Column> = rb.query_decode(&sql, args).await.map_err(query_error)?;
+    if columns.is_empty() {
+        let err_msg = format!("table `{table_name}` does not exist");
+        return Err(Error::SchemaMismatch(err_msg).into());
+    }
+    let (sql, args) = dialect.unique_key_columns_sql(table_name)?;
+    let keys: Vec<KeyColumn> = rb.query_decode(&sql, args).await.map_err(query_error)?;
+
+    let problems = problems(&columns, &keys, arity, lengths, tenant);
+    if problems.is_empty() {
+        return Ok(columns.iter().any(|x| x.name.eq_ignore_ascii_case("tenant")));
+    }
+    let err_msg = format!("table `{table_name}` does not fit the adapter: {}", problems.join("; "));
+    Err(Error::SchemaMismatch(err_msg).into())
+}
+
+// the problems of a table with `columns` and unique `keys`, for a rule table of `arity` value columns.
+fn problems(columns: &[Column], keys: &[KeyColumn], arity: usize, lengths: ColumnLengths, tenant: bool) -> Vec<String> {
+    let columns = columns
+        .iter()
+        .map(|x| (x.name.to_lowercase(), x))
+        .collect::<BTreeMap<_, _>>();
+    let mut problems = vec![];
+
+    match columns.get("id") {
+        None => problems.push("column id is missing".to_owned()),
+        Some(id) if !id.data_type.to_lowercase().contains("int") => {
+            problems.push(format!("column id is {}, not an integer", id.data_type))
+        }
+        Some(_) => {}
+    }
+    // the tenant column is part of the unique key once it exists
+    let has_tenant = columns.contains_key("tenant");
+    let mut strings = vec![("ptype".to_owned(), lengths.ptype)];
+    strings.extend((0..arity).map(|i| (format!("v{i}"), lengths.value)));
+    if tenant || has_tenant {
+        strings.push(("tenant".to_owned(), TENANT_LEN));
+    }
+    for (name, len) in &strings {
+        let Some(column) = columns.get(name) else {
+            problems.push(format!("column {name} is missing"));
+            continue;
+        };
+        let data_type = column.data_type.to_lowercase();
+        if !(data_type.contains("char") || data_type.contains("text")) {
+            problems.push(format!("column {name} is {}, not a string", column.data_type));
+        } else if column.max_length.is_some_and(|x| x < *len as i64) {
+            problems.push(format!(
+                "column {name} holds {} characters, the adapter writes up to {len}",
+                column.max_length.unwrap_or_default()
+            ));
+        }
+        if column.nullable != 0 {
+            problems.push(format!("column {name} is nullable, the adapter writes empty values as ''"));
+        }
+    }
+
+    let mut key_columns = BTreeMap::<&str, BTreeSet<String>>::new();
+    for key in keys {
+        key_columns
+            .entry(&key.name)
+            .or_default()
+            .insert(key.column_name.to_lowercase());
+    }
+    let unique_key = strings.into_iter().map(|(name, _)| name).collect::<BTreeSet<_>>();
+    if !key_columns.values().any(|x| *x == unique_key) {
+        let unique_key = unique_key.into_iter().collect::<Vec<_>>().join(", ");
+        problems.push(format!("no unique key over exactly ({unique_key})"));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: &str, max_length: Option<i64>, nullable: bool) -> Column {
+        Column {
+            name: name.to_owned(),
+            data_type: data_type.to_owned(),
+            max_length,
+            nullable: nullable as i64,
+        }
+    }
+
+    fn key(column_name: &str) -> KeyColumn {
+        KeyColumn {
+            name: "unique_key_casbin_rule".to_owned(),
+            column_name: column_name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_problems() {
+        let mut columns = vec![
+            column("id", "int", None, false),
+            column("ptype", "varchar", Some(12), false),
+            column("v0", "varchar", Some(128), false),
+            column("v1", "varchar", Some(128), false),
+        ];
+        let mut keys = vec![key("ptype"), key("v0"), key("v1")];
+        let lengths = ColumnLengths::default();
+        assert!(problems(&columns, &keys, 2, lengths, false).is_empty());
+        assert_eq!(
+            problems(&columns, &keys, 2, lengths, true),
+            vec!["column tenant is missing", "no unique key over exactly (ptype, tenant, v0, v1)"]
+        );
+
+        columns[2] = column("V0", "varchar", Some(64), true);
+        columns[3] = column("v1", "integer", None, false);
+        keys.pop();
+        assert_eq!(
+            problems(&columns, &keys, 3, lengths, false),
+            vec![
+                "column v0 holds 64 characters, the adapter writes up to 128",
+                "column v0 is nullable, the adapter writes empty values as ''",
+                "column v1 is integer, not a string",
+                "column v2 is missing",
+                "no unique key over exactly (ptype, v0, v1, v2)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! A conformance suite of the adapter against in-memory SQLite, for the adapter configurations of your own code.
+//!
+//! Every case runs on a new database with an adapter built by your factory, `db_sync` is called before it.
+//! A failed check panics like `assert!`, the database errors are returned.
+//! ```rust,ignore
+//! #[tokio::test]
+//! async fn test_conformance() {
+//!     testing::run_all(|rb| async move { Ok(RbatisAdapter::new(&rb).await?.with_table_name("svc_a_casbin_rule")) })
+//!         .await
+//!         .unwrap();
+//! }
+//! ```
+use std::future::Future;
+
+use casbin::{Adapter, DefaultModel, Filter, Model, Result};
+use rbatis::RBatis;
+use rbdc_sqlite::driver::SqliteDriver;
+
+use crate::{to_vec, RbatisAdapter};
+
+/// The model of the cases, rbac with domains so the filters have a field to select.
+pub const MODEL: &str = "
+[request_definition]
+r = sub, dom, obj, act
+
+[policy_definition]
+p = sub, dom, obj, act
+
+[role_definition]
+g = _, _, _
+
+[policy_effect]
+e = some(where (p.eft == allow))
+
+[matchers]
+m = g(r.sub, p.sub, r.dom) && r.dom == p.dom && r.obj == p.obj && r.act == p.act
+";
+
+/// A rbatis pool of a new in-memory SQLite database, with a single connection as every connection opens its own database.
+pub async fn sqlite_rbatis() -> RBatis {
+    let rb = RBatis::new();
+    rb.init(SqliteDriver {}, "sqlite://:memory:").unwrap();
+    rb.get_pool().unwrap().set_max_open_conns(1).await;
+    rb
+}
+
+/// A new model of [`MODEL`] without rules.
+pub async fn model() -> DefaultModel {
+    DefaultModel::from_str(MODEL).await.unwrap()
+}
+
+/// Runs every case with a new adapter from `factory`, on its own database.
+pub async fn run_all<F, Fut>(factory: F) -> Result<()>
+where
+    F: Fn(RBatis) -> Fut,
+    Fut: Future<Output = Result<RbatisAdapter>>,
+{
+    load_policy(adapter(&factory).await?).await?;
+    load_filtered_policy(adapter(&factory).await?).await?;
+    add_remove_policy(adapter(&factory).await?).await?;
+    remove_filtered_policy(adapter(&factory).await?).await?;
+    save_clear_policy(adapter(&factory).await?).await?;
+    empty_fields(adapter(&factory).await?).await?;
+    Ok(())
+}
+
+// an adapter on a new database, with its tables.
+async fn adapter<F, Fut>(factory: &F) -> Result<RbatisAdapter>
+where
+    F: Fn(RBatis) -> Fut,
+    Fut: Future<Output = Result<RbatisAdapter>>,
+{
+    let adapter = factory(sqlite_rbatis().await).await?;
+    adapter.db_sync().await?;
+    Ok(adapter)
+}
+
+// the rules of both sections, sorted to compare them regardless of the load order.
+fn sorted_rules(m: &DefaultModel) -> (Vec<Vec<String>>, Vec<Vec<String>>) {
+    let mut p = m.get_policy("p", "p");
+    let mut g = m.get_policy("g", "g");
+    p.sort();
+    g.sort();
+    (p, g)
+}
+
+/// The added rules of both sections are loaded.
+pub async fn load_policy(mut adapter: RbatisAdapter) -> Result<()> {
+    let p = vec![
+        to_vec!["admin", "domain1", "data1", "read"],
+        to_vec!["admin", "domain2", "data2", "write"],
+    ];
+    let g = vec![to_vec!["alice", "admin", "domain1"], to_vec!["bob", "admin", "domain2"]];
+    assert!(adapter.add_policies("p", "p", p.clone()).await?);
+    assert!(adapter.add_policies("g", "g", g.clone()).await?);
+
+    let mut m = model().await;
+    adapter.load_policy(&mut m).await?;
+    assert_eq!(sorted_rules(&m), (p, g));
+    assert!(!adapter.is_filtered());
+    Ok(())
+}
+
+/// Only the rules which match the filter are loaded, and the adapter is marked as filtered.
+pub async fn load_filtered_policy(mut adapter: RbatisAdapter) -> Result<()> {
+    adapter
+        .add_policies(
+            "p",
+            "p",
+            vec![
+                to_vec!["admin", "domain1", "data1", "read"],
+                to_vec!["admin", "domain2", "data2", "read"],
+            ],
+        )
+        .await?;
+    adapter
+        .add_policies("g", "g", vec![to_vec!["alice", "admin", "domain1"], to_vec!["bob", "admin", "domain2"]])
+        .await?;
+
+    let mut m = model().await;
+    let filter = Filter {
+        p: vec!["", "domain1"],
+        g: vec!["", "", "domain1"],
+    };
+    adapter.load_filtered_policy(&mut m, filter).await?;
+    assert!(adapter.is_filtered());
+    assert_eq!(
+        sorted_rules(&m),
+        (
+            vec![to_vec!["admin", "domain1", "data1", "read"]],
+            vec![to_vec!["alice", "admin", "domain1"]]
+        )
+    );
+    Ok(())
+}
+
+/// A rule is removed once, removing a rule which is not stored returns `false`.
+pub async fn add_remove_policy(mut adapter: RbatisAdapter) -> Result<()> {
+    let rule = to_vec!["alice", "domain1", "data1", "read"];
+    assert!(adapter.add_policy("p", "p", rule.clone()).await?);
+    assert!(adapter.remove_policy("p", "p", rule.clone()).await?);
+    assert!(!adapter.remove_policy("p", "p", rule.clone()).await?);
+
+    let rules = vec![
+        to_vec!["alice", "domain1", "data1", "read"],
+        to_vec!["bob", "domain1", "data1", "write"],
+    ];
+    assert!(adapter.add_policies("p", "p", rules.clone()).await?);
+    assert!(adapter.remove_policies("p", "p", rules).await?);
+    let mut m = model().await;
+    adapter.load_policy(&mut m).await?;
+    assert!(m.get_policy("p", "p").is_empty());
+    Ok(())
+}
+
+/// The rules whose fields from `field_index` on match are removed, an empty value matches any value.
+pub async fn remove_filtered_policy(mut adapter: RbatisAdapter) -> Result<()> {
+    let rules = vec![
+        to_vec!["alice", "domain1", "data1", "read"],
+        to_vec!["alice", "domain1", "data2", "write"],
+        to_vec!["bob", "domain1", "data1", "read"],
+        to_vec!["bob", "domain2", "data1", "read"],
+    ];
+    adapter.add_policies("p", "p", rules).await?;
+    assert!(adapter.remove_filtered_policy("p", "p", 1, to_vec!["domain1", "", "read"]).await?);
+    assert!(!adapter.remove_filtered_policy("p", "p", 1, to_vec!["domain1", "", "read"]).await?);
+    // the ptype is part of the filter
+    assert!(!adapter.remove_filtered_policy("g", "g", 0, to_vec!["alice"]).await?);
+
+    let mut m = model().await;
+    adapter.load_policy(&mut m).await?;
+    assert_eq!(
+        sorted_rules(&m).0,
+        vec![
+            to_vec!["alice", "domain1", "data2", "write"],
+            to_vec!["bob", "domain2", "data1", "read"],
+        ]
+    );
+    Ok(())
+}
+
+/// `save_policy` replaces the stored rules with the rules of the model, `clear_policy` removes them.
+pub async fn save_clear_policy(mut adapter: RbatisAdapter) -> Result<()> {
+    adapter.add_policy("p", "p", to_vec!["stale", "domain1", "data1", "read"]).await?;
+    let mut m = model().await;
+    m.add_policy("p", "p", to_vec!["alice", "domain1", "data1", "read"]);
+    m.add_policy("g", "g", to_vec!["bob", "alice", "domain1"]);
+    adapter.save_policy(&mut m).await?;
+
+    let mut loaded = model().await;
+    adapter.load_policy(&mut loaded).await?;
+    assert_eq!(sorted_rules(&loaded), sorted_rules(&m));
+
+    adapter.clear_policy().await?;
+    let mut loaded = model().await;
+    adapter.load_policy(&mut loaded).await?;
+    assert_eq!(sorted_rules(&loaded), (vec![], vec![]));
+    Ok(())
+}
+
+/// The empty fields in the middle of a rule are kept, the trailing ones are dropped.
+pub async fn empty_fields(mut adapter: RbatisAdapter) -> Result<()> {
+    let rule = to_vec!["alice", "", "data1", "read"];
+    assert!(adapter.add_policy("p", "p", rule.clone()).await?);
+    assert!(adapter.add_policy("p", "p", to_vec!["bob", "domain1"]).await?);
+
+    let mut m = model().await;
+    adapter.load_policy(&mut m).await?;
+    assert_eq!(sorted_rules(&m).0, vec![rule.clone(), to_vec!["bob", "domain1"]]);
+
+    assert!(adapter.remove_policy("p", "p", rule).await?);
+    assert!(adapter.remove_filtered_policy("p", "p", 0, to_vec!["bob", "domain1", ""]).await?);
+    let mut m = model().await;
+    adapter.load_policy(&mut m).await?;
+    assert!(m.get_policy("p", "p").is_empty());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConflictStrategy, SaveMode};
+
+    #[tokio::test]
+    async fn test_conformance() {
+        run_all(|rb| async move { RbatisAdapter::new(&rb).await }).await.unwrap();
+        run_all(|rb| async move {
+            Ok(RbatisAdapter::new(&rb)
+                .await?
+                .with_table_name("svc_a_casbin_rule")
+                .with_arity(8)
+                .with_save_mode(SaveMode::Diff)
+                .with_conflict_strategy(ConflictStrategy::Skip)
+                .with_revision_tracking(true)
+                .with_audit(true)
+                .with_page_size(1))
+        })
+        .await
+        .unwrap();
+        run_all(|rb| async move { Ok(RbatisAdapter::new(&rb).await?.for_tenant("acme")) })
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! The writes of the adapter in a transaction of the caller.
+use casbin::{Model, Result};
+use rbatis::executor::Executor;
+
+use crate::actions as adapter;
+use crate::filter::PolicyFilter;
+use crate::models::SaveMode;
+use crate::utils::normalize_policy;
+use crate::RbatisAdapter;
+
+/// The mutation APIs of a [`RbatisAdapter`] on a transaction of the caller, see [`RbatisAdapter::in_transaction`].
+///
+/// The writes do not begin, commit or roll back a transaction, so they commit or roll back together
+/// with the other writes of the caller. They are tracked and audited like the writes of the adapter,
+/// and scoped to its tenant. The caller commits, so it calls [`RbatisAdapter::mark_write`] after the commit.
+/// On postgres a failed statement aborts the transaction, use
+/// [`ConflictStrategy::Skip`](crate::ConflictStrategy::Skip) to add rules which may be stored.
+pub struct TxAdapter<'a> {
+    adapter: &'a RbatisAdapter,
+    tx: &'a dyn Executor,
+}
+
+impl<'a> TxAdapter<'a> {
+    pub(crate) fn new(adapter: &'a RbatisAdapter, tx: &'a dyn Executor) -> Self {
+        Self { adapter, tx }
+    }
+
+    // the transaction of the writes, a write on the primary.
+    fn writer(&self) -> &'a dyn Executor {
+        self.tx
+    }
+
+    /// Like `Adapter::add_policy`.
+    pub async fn add_policy(&self, _sec: &str, ptype: &str, rule: Vec<String>) -> Result<bool> {
+        match self.adapter.rule_row(ptype, &rule)? {
+            Some(new_rule) => {
+                adapter::add_policies_in(self.writer(), &self.adapter.write_table()?, vec![new_rule], self.adapter.conflict).await
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Like `Adapter::add_policies`.
+    pub async fn add_policies(&self, _sec: &str, ptype: &str, rules: Vec<Vec<String>>) -> Result<bool> {
+        let new_rules = self.adapter.policy_rows(ptype, &rules)?;
+        adapter::add_policies_in(self.writer(), &self.adapter.write_table()?, new_rules, self.adapter.conflict).await
+    }
+
+    /// Like [`RbatisAdapter::add_new_policies`].
+    pub async fn add_new_policies(&self, ptype: &str, rules: Vec<Vec<String>>) -> Result<Vec<Vec<String>>> {
+        let new_rules = self.adapter.policy_rows(ptype, &rules)?;
+        let inserted =
+            adapter::add_new_policies_in(self.writer(), &self.adapter.write_table()?, new_rules, self.adapter.conflict).await?;
+        Ok(self.adapter.inserted_rules(rules, inserted))
+    }
+
+    /// Like `Adapter::remove_policy`.
+    pub async fn remove_policy(&self, sec: &str, ptype: &str, rule: Vec<String>) -> Result<bool> {
+        self.remove_policies(sec, ptype, vec![rule]).await
+    }
+
+    /// Like `Adapter::remove_policies`.
+    pub async fn remove_policies(&self, _sec: &str, ptype: &str, rules: Vec<Vec<String>>) -> Result<bool> {
+        self.adapter.check_rules(ptype, &rules)?;
+        adapter::remove_policies_in(self.writer(), &self.adapter.write_table()?, ptype, rules).await
+    }
+
+    /// Like `Adapter::remove_filtered_policy`.
+    pub async fn remove_filtered_policy(
+        &self,
+        _sec: &str,
+        ptype: &str,
+        field_index: usize,
+        field_values: Vec<String>,
+    ) -> Result<bool> {
+        if !self.adapter.fits_filter(field_index, &field_values)? {
+            return Ok(false);
+        }
+        adapter::remove_filtered_policy_in(self.writer(), &self.adapter.write_table()?, ptype, field_index, field_values).await
+    }
+
+    /// Like [`RbatisAdapter::remove_where`].
+    pub async fn remove_where(&self, filter: &PolicyFilter) -> Result<u64> {
+        adapter::remove_where_in(self.writer(), &self.adapter.write_table()?, filter).await
+    }
+
+    /// Like [`RbatisAdapter::update_policy`].
+    pub async fn update_policy(&self, sec: &str, ptype: &str, old_rule: Vec<String>, new_rule: Vec<String>) -> Result<bool> {
+        let updated = self.update_policies(sec, ptype, vec![old_rule], vec![new_rule]).await?;
+        Ok(updated > 0)
+    }
+
+    /// Like [`RbatisAdapter::update_policies`].
+    pub async fn update_policies(
+        &self,
+        _sec: &str,
+        ptype: &str,
+        old_rules: Vec<Vec<String>>,
+        new_rules: Vec<Vec<String>>,
+    ) -> Result<u64> {
+        let pairs = self.adapter.update_pairs(ptype, old_rules, new_rules)?;
+        adapter::update_policies_in(self.writer(), &self.adapter.write_table()?, ptype, pairs).await
+    }
+
+    /// Like [`RbatisAdapter::update_filtered_policies`].
+    pub async fn update_filtered_policies(
+        &self,
+        _sec: &str,
+        ptype: &str,
+        new_rules: Vec<Vec<String>>,
+        field_index: usize,
+        field_values: Vec<String>,
+    ) -> Result<Vec<Vec<String>>> {
+        self.adapter.check_filter(field_index, &field_values)?;
+        let new_rules = self.adapter.policy_rows(ptype, &new_rules)?;
+        let table = self.adapter.write_table()?;
+        let old_rules =
+            adapter::update_filtered_policies_in(self.writer(), &table, ptype, new_rules, field_index, field_values).await?;
+        Ok(old_rules.iter().filter_map(normalize_policy).collect())
+    }
+
+    /// Like `Adapter::save_policy`, with the save mode of the adapter.
+    pub async fn save_policy(&self, m: &dyn Model) -> Result<()> {
+        let rules = self.adapter.model_rules(m)?;
+        let table = self.adapter.write_table()?;
+        match self.adapter.save_mode {
+            SaveMode::Replace => adapter::save_policy_in(self.writer(), &table, rules).await,
+            SaveMode::Diff => adapter::save_policy_diff_in(self.writer(), &table, rules).await.map(|_| ()),
+        }
+    }
+
+    /// Like `Adapter::clear_policy`.
+    pub async fn clear_policy(&self) -> Result<()> {
+        adapter::clear_policy_in(self.writer(), &self.adapter.write_table()?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{model, sqlite_rbatis};
+    use crate::to_vec;
+    use casbin::Adapter;
+
+    #[tokio::test]
+    async fn test_in_transaction() {
+        let rb = sqlite_rbatis().await;
+        let mut adapter = RbatisAdapter::new(&rb).await.unwrap().with_revision_tracking(true).with_audit(true);
+        adapter.db_sync().await.unwrap();
+        rb.exec("CREATE TABLE users (name VARCHAR(64) NOT NULL)", vec![]).await.unwrap();
+        let rule = to_vec!["alice", "domain1", "data1", "read"];
+
+        // the rule is rolled back with the user
+        let tx = rb.acquire_begin().await.unwrap();
+        tx.exec("INSERT INTO users (name) VALUES ('alice')", vec![]).await.unwrap();
+        assert!(adapter.in_transaction(&tx).add_policy("p", "p", rule.clone()).await.unwrap());
+        tx.rollback().await.unwrap();
+        // the single connection of the pool is released with the transaction
+        drop(tx);
+        let mut m = model().await;
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+        assert_eq!(adapter.revision().await.unwrap(), 0);
+        assert!(adapter.audit_log(0, 10).await.unwrap().is_empty());
+
+        // and committed with it
+        let tx = rb.acquire_begin().await.unwrap();
+        tx.exec("INSERT INTO users (name) VALUES ('alice')", vec![]).await.unwrap();
+        let writes = adapter.in_transaction(&tx);
+        assert!(writes.add_policy("p", "p", rule.clone()).await.unwrap());
+        assert!(writes.update_policy("p", "p", rule, to_vec!["alice", "domain1", "data1", "write"]).await.unwrap());
+        tx.commit().await.unwrap();
+        drop(tx);
+        let mut m = model().await;
+        adapter.load_policy(&mut m).await.unwrap();
+        assert_eq!(m.get_policy("p", "p"), vec![to_vec!["alice", "domain1", "data1", "write"]]);
+        assert_eq!(adapter.revision().await.unwrap(), 2);
+        assert_eq!(adapter.audit_log(0, 10).await.unwrap().len(), 3);
+
+        let tx = rb.acquire_begin().await.unwrap();
+        adapter.in_transaction(&tx).clear_policy().await.unwrap();
+        tx.commit().await.unwrap();
+        drop(tx);
+        let mut m = model().await;
+        adapter.load_policy(&mut m).await.unwrap();
+        assert!(m.get_policy("p", "p").is_empty());
+    }
+}
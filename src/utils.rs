@@ -1,65 +1,59 @@
-#![allow(clippy::get_first)]
-use crate::models::CasbinRule;
+use casbin::Result;
 
-// converts the policy vec (6 elements) to a CasbinRule struct.
-pub(crate) fn save_policy_line(ptype: &str, rule: &[String]) -> Option<CasbinRule> {
+use crate::error::Error;
+
+use crate::models::{CasbinRule, ColumnLengths};
+
+// converts the policy vec to a CasbinRule struct, the values are filled up to `arity` with empty strings.
+// a rule wider than `arity` is an error, it can not be stored without losing fields,
+// and so is a ptype or value longer than its column in `lengths`.
+pub(crate) fn save_policy_line(ptype: &str, rule: &[String], arity: usize, lengths: ColumnLengths) -> Result<Option<CasbinRule>> {
     if ptype.trim().is_empty() || rule.is_empty() {
-        return None;
+        return Ok(None);
     }
-    Some(CasbinRule {
+    if rule.len() > arity {
+        let err_msg = format!(
+            "rule `{}, {}` has {} fields, but the adapter stores at most {} (v0..v{})",
+            ptype,
+            rule.join(", "),
+            rule.len(),
+            arity,
+            arity - 1
+        );
+        return Err(Error::SchemaMismatch(err_msg).into());
+    }
+    // the lengths of the columns are in characters
+    let fields = std::iter::once(("ptype".to_owned(), ptype, lengths.ptype))
+        .chain(rule.iter().enumerate().map(|(i, x)| (format!("v{i}"), x.as_str(), lengths.value)));
+    for (field, value, max) in fields {
+        let len = value.chars().count();
+        if len > max {
+            return Err(Error::ValueTooLong {
+                ptype: ptype.to_owned(),
+                rule: rule.to_vec(),
+                field,
+                len,
+                max,
+            }
+            .into());
+        }
+    }
+    let mut values = rule.to_vec();
+    values.resize(arity, String::new());
+    Ok(Some(CasbinRule {
         id: None,
-        ptype: Some(ptype.to_owned()),
-        v0: rule.get(0).cloned().or(Some("".to_owned())),
-        v1: rule.get(1).cloned().or(Some("".to_owned())),
-        v2: rule.get(2).cloned().or(Some("".to_owned())),
-        v3: rule.get(3).cloned().or(Some("".to_owned())),
-        v4: rule.get(4).cloned().or(Some("".to_owned())),
-        v5: rule.get(5).cloned().or(Some("".to_owned())),
-    })
+        ptype: ptype.to_owned(),
+        values,
+    }))
 }
 
 // converts the CasbinRule struct to a policy vec (if it has any values).
+// only the trailing empty values are dropped, so empty fields in the middle are kept.
 pub(crate) fn normalize_policy(casbin_rule: &CasbinRule) -> Option<Vec<String>> {
-    let mut result = vec![];
-
-    if let Some(v) = &casbin_rule.v0 {
-        if !v.is_empty(){
-            result.push(v.to_owned());
-        }
-    };
-    if let Some(v) = &casbin_rule.v1 {
-        if !v.is_empty(){
-            result.push(v.to_owned());
-        }
-    };
-    if let Some(v) = &casbin_rule.v2 {
-        if !v.is_empty(){
-            result.push(v.to_owned());
-        }
-    };
-    if let Some(v) = &casbin_rule.v3 {
-        if !v.is_empty(){
-            result.push(v.to_owned());
-        }
-    };
-    if let Some(v) = &casbin_rule.v4 {
-        if !v.is_empty(){
-            result.push(v.to_owned());
-        }
-    };
-    if let Some(v) = &casbin_rule.v5 {
-        if !v.is_empty(){
-            result.push(v.to_owned());
-        }
-    };
-    if result.is_empty() {
-        return None;
-    }
-    Some(result)
-    
+    let len = casbin_rule.values.iter().rposition(|v| !v.is_empty())? + 1;
+    Some(casbin_rule.values[..len].to_vec())
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -68,31 +62,56 @@ mod tests {
     #[test]
     fn test_save_policy_line() {
         let rule = to_vec!["alice", "data1", "read"];
-        let casbin_rule = save_policy_line("p", &rule).unwrap();
-        assert_eq!(casbin_rule.ptype, Some("p".to_owned()));
-        assert_eq!(casbin_rule.v0, Some("alice".to_owned()));
-        assert_eq!(casbin_rule.v1, Some("data1".to_owned()));
-        assert_eq!(casbin_rule.v2, Some("read".to_owned()));
-        assert_eq!(casbin_rule.v3, Some("".to_owned()));
-        assert_eq!(casbin_rule.v4, Some("".to_owned()));
-        assert_eq!(casbin_rule.v5, Some("".to_owned()));
+        let lengths = ColumnLengths::default();
+        let casbin_rule = save_policy_line("p", &rule, 6, lengths).unwrap().unwrap();
+        assert_eq!(casbin_rule.ptype, "p".to_owned());
+        assert_eq!(casbin_rule.values, to_vec!["alice", "data1", "read", "", "", ""]);
+
+        let rule = to_vec!["alice", "data1", "read", "allow", "t1", "t2", "t3"];
+        assert!(save_policy_line("p", &rule, 6, lengths).is_err());
+        let casbin_rule = save_policy_line("p", &rule, 8, lengths).unwrap().unwrap();
+        assert_eq!(casbin_rule.values.len(), 8);
+
+        assert!(save_policy_line("", &rule, 8, lengths).unwrap().is_none());
+        assert!(save_policy_line("p", &[], 8, lengths).unwrap().is_none());
+
+        // the lengths are counted in characters
+        let rule = vec!["alice".to_owned(), "ü".repeat(128), "é".repeat(129)];
+        let err = save_policy_line("p", &rule, 6, lengths).unwrap_err();
+        assert!(matches!(
+            Error::downcast(&err),
+            Some(Error::ValueTooLong { field, len: 129, max: 128, .. }) if field == "v2"
+        ));
+        let err = save_policy_line("policy_admin_x", &rule[..1], 6, lengths).unwrap_err();
+        let msg = Error::downcast(&err).unwrap().to_string();
+        assert_eq!(msg, "ptype of rule `policy_admin_x, alice` has 14 characters, the column holds at most 12");
+        let lengths = ColumnLengths { ptype: 16, value: 255 };
+        assert!(save_policy_line("policy_admin_x", &rule, 6, lengths).is_ok());
     }
 
     #[test]
     fn test_normalize_policy() {
         let casbin_rule = CasbinRule {
             id: None,
-            ptype: Some("p".to_owned()),
-            v0: Some("alice".to_owned()),
-            v1: Some("data1".to_owned()),
-            v2: Some("read".to_owned()),
-            v3: None,
-            v4: None,
-            v5: Some("".to_owned()),
+            ptype: "p".to_owned(),
+            values: to_vec!["alice", "data1", "read", "", "", ""],
         };
         let policy = normalize_policy(&casbin_rule).unwrap();
         assert_eq!(policy, to_vec!["alice", "data1", "read"]);
-        
-    }
 
-}
\ No newline at end of file
+        let casbin_rule = CasbinRule {
+            id: None,
+            ptype: "p".to_owned(),
+            values: to_vec!["alice", "", "read", "", "", "", "", "x"],
+        };
+        let policy = normalize_policy(&casbin_rule).unwrap();
+        assert_eq!(policy, to_vec!["alice", "", "read", "", "", "", "", "x"]);
+
+        let casbin_rule = CasbinRule {
+            id: None,
+            ptype: "p".to_owned(),
+            values: to_vec!["", ""],
+        };
+        assert!(normalize_policy(&casbin_rule).is_none());
+    }
+}
@@ -0,0 +1,154 @@
+//! A casbin watcher which polls the revision of the rules.
+//!
+//! Every adapter with revision tracking bumps the revision in the transaction of its writes,
+//! the watcher invokes the update callback with the new revision when it changes.
+//! The changes of the local enforcer are reported too, as they bump the same revision.
+use casbin::{EventData, Result, Watcher};
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::RbatisAdapter;
+
+type Callback = Box<dyn FnMut(String) + Send + Sync>;
+
+/// A [`Watcher`] which polls the revision table of an adapter, see [`RbatisAdapter::with_revision_tracking`].
+/// ```rust,ignore
+/// let mut watcher = RbatisWatcher::new(&adapter).await?;
+/// let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+/// watcher.set_update_callback(Box::new(move |revision| {
+///     let _ = tx.send(revision);
+/// }));
+/// watcher.start(Duration::from_secs(1));
+/// e.set_watcher(Box::new(watcher));
+/// while let Some(_revision) = rx.recv().await {
+///     e.load_policy().await?;
+/// }
+/// ```
+pub struct RbatisWatcher {
+    adapter: RbatisAdapter,
+    revision: Arc<AtomicI64>,
+    callback: Arc<Mutex<Option<Callback>>>,
+    poller: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl RbatisWatcher {
+    /// Creates a watcher of the adapter's table, the current revision is the baseline.
+    pub async fn new(adapter: &RbatisAdapter) -> Result<Self> {
+        let revision = adapter.revision().await?;
+        Ok(Self {
+            adapter: adapter.clone(),
+            revision: Arc::new(AtomicI64::new(revision)),
+            callback: Arc::new(Mutex::new(None)),
+            poller: None,
+        })
+    }
+
+    /// The last revision the watcher has seen.
+    pub fn revision(&self) -> i64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    /// Checks the revision once, and invokes the callback when it changed.
+    /// Returns whether it changed, it can be used instead of `start` on any runtime.
+    pub async fn poll(&self) -> Result<bool> {
+        poll(&self.adapter, &self.revision, &self.callback).await
+    }
+
+    /// Polls the revision every `interval` on a tokio task, until the watcher is dropped.
+    /// The failed polls are retried at the next interval.
+    pub fn start(&mut self, interval: Duration) {
+        self.stop();
+        let adapter = self.adapter.clone();
+        let revision = self.revision.clone();
+        let callback = self.callback.clone();
+        self.poller = Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(_err) = poll(&adapter, &revision, &callback).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("failed to poll the casbin rule revision: {}", _err);
+                }
+            }
+        }));
+    }
+
+    /// Stops the background polling.
+    pub fn stop(&mut self) {
+        if let Some(poller) = self.poller.take() {
+            poller.abort();
+        }
+    }
+}
+
+async fn poll(adapter: &RbatisAdapter, revision: &AtomicI64, callback: &Mutex<Option<Callback>>) -> Result<bool> {
+    let current = adapter.revision().await?;
+    if revision.swap(current, Ordering::SeqCst) == current {
+        return Ok(false);
+    }
+    if let Some(callback) = callback.lock().unwrap().as_mut() {
+        callback(current.to_string());
+    }
+    Ok(true)
+}
+
+impl Drop for RbatisWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Watcher for RbatisWatcher {
+    fn set_update_callback(&mut self, cb: Box<dyn FnMut(String) + Send + Sync>) {
+        *self.callback.lock().unwrap() = Some(cb);
+    }
+
+    // the write has already bumped the revision in the database, so there is nothing to publish.
+    fn update(&mut self, _d: EventData) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_vec;
+    use casbin::Adapter;
+    use rbatis::RBatis;
+    use rbdc_sqlite::driver::SqliteDriver;
+
+    #[tokio::test]
+    async fn test_watcher() {
+        let rb = RBatis::new();
+        rb.init(SqliteDriver {}, "sqlite://:memory:").unwrap();
+        rb.get_pool().unwrap().set_max_open_conns(1).await;
+        let replica_a = RbatisAdapter::new(&rb).await.unwrap().with_revision_tracking(true);
+        let mut replica_b = replica_a.clone();
+        replica_a.db_sync().await.unwrap();
+
+        let mut watcher = RbatisWatcher::new(&replica_a).await.unwrap();
+        let revisions = Arc::new(Mutex::new(vec![]));
+        let received = revisions.clone();
+        watcher.set_update_callback(Box::new(move |revision| received.lock().unwrap().push(revision)));
+        assert!(!watcher.poll().await.unwrap());
+
+        replica_b.add_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap();
+        assert!(watcher.poll().await.unwrap());
+        assert!(!watcher.poll().await.unwrap());
+        assert_eq!(watcher.revision(), 1);
+
+        watcher.start(Duration::from_millis(10));
+        replica_b.remove_policy("", "p", to_vec!["alice", "data1", "read"]).await.unwrap();
+        for _ in 0..100 {
+            if revisions.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*revisions.lock().unwrap(), vec!["1", "2"]);
+        watcher.stop();
+    }
+}